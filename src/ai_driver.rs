@@ -3,17 +3,46 @@ use crate::car::CarComponent;
 extern crate serde;
 extern crate serde_json;
 
-use crate::track::Track;
+use crate::car_driver::Autopilot;
+use crate::input::{Action, Controls};
+use crate::menu;
+use crate::race_control::{RaceControl, RacePhase, RaceStatus, Respawn};
+use crate::track::{LoadTrack, PitLane, Track, TrackPath, TrackProjection};
 use glui::mecs::*;
 use glui::tools::serde_tools::SerdeError;
-use glui::tools::{LinSpace, Randable, Smoothstep, Vec2};
-use std::time::{Duration, Instant};
+use glui::tools::{LinSpace, Smoothstep, Vec2};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use std::time::Duration;
+
+const STRAIGHT_LENGTH: f32 = 150.0; // distance looked ahead for a straight (m)
+const STRAIGHT_TURN: f32 = 0.2; // largest heading change still called a straight (rad)
+const PIT_DECISION_DISTANCE: f32 = 300.0; // distance before the pit entry to decide on a stop (m)
+const PIT_TURN_IN: f32 = 40.0; // distance before the pit entry to head for the lane (m)
+const PIT_LOOK_AHEAD: f32 = 10.0; // m
+const PIT_DECELERATION: f32 = 4.0; // braking planned when slowing for the pits (m/s^2)
+const PIT_OVERSHOOT: f32 = 5.0; // distance past the box after which the stop is abandoned (m)
+const PIT_FUEL_MARGIN: f32 = 1.3; // laps of fuel below which a stop is due
+const PIT_TIRE_WEAR: f32 = 0.6; // tire wear above which a stop is due
+const PIT_DAMAGE: f32 = 0.5; // damage of a zone above which a stop is due
+const FUEL_SAMPLE_DISTANCE: f32 = 500.0; // distance driven before trusting the measured consumption (m)
+const TYPICAL_SPEED: f32 = 40.0; // m/s
+const STUCK_SPEED: f32 = 2.0; // speed below which the car counts as stuck (m/s)
+const STUCK_TIME: f32 = 4.0; // time stuck or facing backwards before respawning (s)
+const TELEPORT_DISTANCE: f32 = 10.0; // jump between frames after which the line is resynced (m)
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PitPlan {
+    Racing,
+    Boxing(usize), // heading for the box, with the number of stops made before
+    Leaving,
+}
 
 struct AiTarget {
     pub offset: Vec2,
     pub steer_coef: f32,
-    pub timer: Instant,
-    pub next_update: Duration,
+    pub age: f32,         // time since the target was picked (s)
+    pub next_update: f32, // s
 }
 
 impl AiTarget {
@@ -21,8 +50,8 @@ impl AiTarget {
         AiTarget {
             offset: Vec2::zero(),
             steer_coef: 2.0,
-            timer: Instant::now(),
-            next_update: Duration::from_millis(1500),
+            age: 0.0,
+            next_update: 1.5,
         }
     }
 }
@@ -30,15 +59,101 @@ impl AiTarget {
 pub struct AiDriver {
     car: Entity,
     track_points: Vec<Vec2>,
+    lane: f32,
     next_pt: usize,
     target: AiTarget,
+    reaction_time: f32,
+    rng: XorShiftRng,
+    controls: Controls,
+    path: TrackPath,
+    pit_lane: Option<PitLane>,
+    pit_plan: PitPlan,
+    fuel_mark: Option<(f32, f32)>, // progress and fuel the consumption is measured from
+    last_pos: Option<Vec2>,
+    stuck_time: f32,
+    autopilot: bool, // drives the car of a player, only while it is on autopilot
 }
 
 impl System for AiDriver {
-    fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
-        let mut car = world.component_mut::<CarComponent>(self.car).unwrap();
+    fn receive(&mut self, msg: &Box<dyn Message>, _world: &mut StaticWorld) {
+        if let Some(LoadTrack(file)) = msg.downcast_ref::<LoadTrack>() {
+            match (
+                Self::track_points(file, self.lane),
+                Track::centreline(file),
+                Track::pit_lane(file),
+            ) {
+                (Ok(pts), Ok(path), Ok(pit_lane)) => {
+                    self.track_points = pts;
+                    self.next_pt = 0;
+                    self.path = path;
+                    self.pit_lane = pit_lane;
+                    self.pit_plan = PitPlan::Racing;
+                    self.fuel_mark = None;
+                    self.last_pos = None;
+                    self.stuck_time = 0.0;
+                }
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    println!("Failed to read path of {}: {:?}", file, e)
+                }
+            }
+        }
+    }
+
+    fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) || !self.driving(world) {
+            return;
+        }
+        let dt = delta_time.as_secs_f32();
+        let (throttle_limit, since_green) = world
+            .component::<RaceStatus>(self.car)
+            .map(|s| (s.throttle_limit(), s.since_green))
+            .unwrap_or((1.0, Some(f32::INFINITY)));
+        let race = world
+            .component::<RaceStatus>(self.car)
+            .filter(|s| s.since_green.is_some())
+            .map(|s| (s.progress, s.race_laps, s.pit_stops.len()));
+        let racing = world
+            .component::<RaceStatus>(self.car)
+            .map(|s| {
+                matches!(
+                    s.phase,
+                    RacePhase::Green | RacePhase::Running | RacePhase::Chequered
+                ) && !s.in_pit_lane
+                    && s.finish_time.is_none()
+            })
+            .unwrap_or(false);
+        let mut car = match world.component_mut::<CarComponent>(self.car) {
+            Some(car) => car,
+            None => return,
+        };
 
-        let target = self.track_points[self.next_pt] + self.target.offset;
+        // pick the racing line up again after the car was moved, by a respawn or a restart
+        if let Some(last) = self.last_pos {
+            if (car.position - last).length() > TELEPORT_DISTANCE {
+                self.resync(car.position, car.dir());
+                self.stuck_time = 0.0;
+            }
+        }
+        self.last_pos = Some(car.position);
+
+        let proj = self.path.project(car.position);
+
+        // stuck against something or spun around
+        if racing
+            && self.pit_plan == PitPlan::Racing
+            && (car.speed() < STUCK_SPEED || car.dir().dot(proj.dir) < 0.0)
+        {
+            self.stuck_time += dt;
+        } else {
+            self.stuck_time = 0.0;
+        }
+        let stuck = self.stuck_time > STUCK_TIME;
+        let (pit_target, max_speed) = match race {
+            Some(race) => self.plan_pit(car, &proj, race),
+            None => (None, f32::INFINITY),
+        };
+
+        let target = pit_target.unwrap_or(self.track_points[self.next_pt] + self.target.offset);
         let v = target - car.position;
         let d = car.dir();
 
@@ -47,31 +162,47 @@ impl System for AiDriver {
         car.brake = 0.0;
         car.throttle = car.speed().smoothstep(46.0, 48.0) * -0.5 + 1.0;
 
-        if v.length() < 3.0 {
+        // hold still until the driver reacts to the lights going out
+        if since_green.map(|t| t < self.reaction_time).unwrap_or(true) {
+            car.throttle = 0.0;
+        }
+        car.throttle = car.throttle.min(throttle_limit);
+
+        // slow down for the pit lane and the box
+        if car.speed() > max_speed {
+            car.throttle = 0.0;
+            car.brake = ((car.speed() - max_speed) / 3.0).min(1.0);
+        }
+
+        // boost on straights, saving some nitro before starting to burn it
+        let straight = self.turn_ahead(STRAIGHT_LENGTH) < STRAIGHT_TURN && pit_target.is_none();
+        let reserve = car.boosting || car.boost_level() > 0.5;
+        car.nitro =
+            straight && reserve && car.throttle >= 1.0 && car.speed() > 15.0 && car.speed() < 44.0;
+
+        if v.length() < 3.0 && pit_target.is_none() {
             self.next_pt = (self.next_pt + 1) % self.track_points.len();
         }
 
-        if self.target.timer.elapsed() > self.target.next_update {
-            self.target.offset = Vec2::unit_rand() * 1.0 - Vec2::new(1.0, 1.0) * 0.5;
-            self.target.steer_coef = f32::unit_rand() * 1.0 + 1.5;
-            self.target.next_update = Duration::from_secs_f32(f32::unit_rand() * 0.1 + 0.9);
-            self.target.timer = Instant::now();
+        self.target.age += dt;
+        if self.target.age > self.target.next_update {
+            let rng = &mut self.rng;
+            self.target.offset = Vec2::new(rng.gen(), rng.gen()) - Vec2::new(1.0, 1.0) * 0.5;
+            self.target.steer_coef = rng.gen::<f32>() + 1.5;
+            self.target.next_update = rng.gen::<f32>() * 0.1 + 0.9;
+            self.target.age = 0.0;
+        }
+
+        if stuck {
+            self.stuck_time = 0.0;
+            world.send_by_type::<RaceControl, _>(Respawn(self.car));
         }
     }
 
     fn window_event(&mut self, event: &GlutinWindowEvent, world: &mut StaticWorld) -> bool {
-        let car = world.component_mut::<CarComponent>(self.car).unwrap();
-
-        if let GlutinWindowEvent::KeyboardInput { input, .. } = event {
-            let press = input.state == GlutinElementState::Pressed;
-            if let Some(key) = input.virtual_keycode {
-                if key == GlutinKey::R && !press {
-                    car.position = Vec2::origin();
-                    car.velocity = Vec2::zero();
-                    car.acceleration = Vec2::zero();
-                    car.heading = 0.0;
-                    car.angular_velocity = 0.0;
-                }
+        if let Some((action, press)) = self.controls.action(event, None) {
+            if action == Action::ResetAiCars && !press && self.driving(world) {
+                world.send_by_type::<RaceControl, _>(Respawn(self.car));
             }
         }
 
@@ -79,9 +210,172 @@ impl System for AiDriver {
     }
 }
 
+/// Whether the car should stop at the pits before the end of the race, with its fuel use
+/// measured from the progress and fuel of `fuel_mark` once it drove far enough.
+fn needs_pit(
+    car: &CarComponent,
+    fuel_mark: Option<(f32, f32)>,
+    progress: f32,
+    race_laps: usize,
+    length: f32,
+) -> bool {
+    let remaining = race_laps as f32 * length - progress;
+
+    let burn = match fuel_mark {
+        Some((from, fuel)) if progress - from > FUEL_SAMPLE_DISTANCE => {
+            (fuel - car.fuel) / (progress - from)
+        }
+        _ => car.config.fuel.burn_rate / TYPICAL_SPEED,
+    };
+    let range = car.fuel / burn.max(1e-6);
+    let short_of_fuel = range < remaining && range < length * PIT_FUEL_MARGIN;
+    let worn_out = car.tires.iter().any(|t| t.wear > PIT_TIRE_WEAR) && remaining > 2.0 * length;
+
+    let wrecked = car.damage.worst() > PIT_DAMAGE && remaining > length;
+
+    short_of_fuel || worn_out || wrecked
+}
+
 impl AiDriver {
-    pub fn new(car_entity: Entity, lane: f32) -> Result<AiDriver, SerdeError> {
-        let data = Track::track_curve_control_points()?;
+    /// The driver draws its reaction time and line wobble from its own generator seeded by
+    /// `rng`, so a seeded session drives the same way again.
+    pub fn new<R: Rng>(
+        car_entity: Entity,
+        lane: f32,
+        track_file: &str,
+        controls: Controls,
+        rng: &mut R,
+    ) -> Result<AiDriver, SerdeError> {
+        let mut rng = XorShiftRng::seed_from_u64(rng.gen());
+        Ok(AiDriver {
+            path: Track::centreline(track_file)?,
+            pit_lane: Track::pit_lane(track_file)?,
+            pit_plan: PitPlan::Racing,
+            fuel_mark: None,
+            last_pos: None,
+            stuck_time: 0.0,
+            car: car_entity,
+            track_points: Self::track_points(track_file, lane)?,
+            lane,
+            next_pt: 0,
+            target: AiTarget::new(),
+            reaction_time: rng.gen::<f32>() * 0.3 + 0.15,
+            rng,
+            controls,
+            autopilot: false,
+        })
+    }
+
+    /// Makes the driver stand in for a player, driving their car while it is on autopilot.
+    pub fn for_autopilot(mut self) -> AiDriver {
+        self.autopilot = true;
+        self
+    }
+
+    fn driving(&self, world: &StaticWorld) -> bool {
+        !self.autopilot || world.component::<Autopilot>(self.car).is_some()
+    }
+
+    /// Decides on pit stops and drives through the pit lane, returning the point to head for
+    /// and the speed not to exceed while doing so.
+    fn plan_pit(
+        &mut self,
+        car: &CarComponent,
+        proj: &TrackProjection,
+        (progress, race_laps, stops): (f32, usize, usize),
+    ) -> (Option<Vec2>, f32) {
+        let pit = match self.pit_lane {
+            Some(pit) => pit,
+            None => return (None, f32::INFINITY),
+        };
+        let length = self.path.length();
+        let to_entry = (pit.entry - proj.progress).rem_euclid(length);
+        let in_lane = pit.contains(proj.progress, length);
+        if self.fuel_mark.is_none() {
+            self.fuel_mark = Some((progress, car.fuel));
+        }
+
+        match self.pit_plan {
+            PitPlan::Racing => {
+                if to_entry < PIT_DECISION_DISTANCE
+                    && !in_lane
+                    && needs_pit(car, self.fuel_mark, progress, race_laps, length)
+                {
+                    self.pit_plan = PitPlan::Boxing(stops);
+                } else {
+                    return (None, f32::INFINITY);
+                }
+            }
+            PitPlan::Boxing(before) if stops > before => self.pit_plan = PitPlan::Leaving,
+            PitPlan::Boxing(_) if in_lane && pit.to_box(proj.progress, length) < -PIT_OVERSHOOT => {
+                // overshot the box, give up on this stop
+                self.pit_plan = PitPlan::Leaving;
+            }
+            PitPlan::Leaving if !in_lane => {
+                self.pit_plan = PitPlan::Racing;
+                self.fuel_mark = Some((progress, car.fuel));
+                self.resync(car.position, car.dir());
+                return (None, f32::INFINITY);
+            }
+            _ => {}
+        }
+
+        let limit = pit.speed_limit - 1.0;
+        if !in_lane {
+            // braking into the pit entry
+            let speed = (limit * limit + 2.0 * PIT_DECELERATION * to_entry).sqrt();
+            let target = if to_entry < PIT_TURN_IN {
+                Some(pit.lane_point(&self.path, pit.entry + PIT_LOOK_AHEAD).0)
+            } else {
+                None
+            };
+            return (target, speed);
+        }
+
+        let speed = match self.pit_plan {
+            PitPlan::Boxing(_) => {
+                let to_box = pit.to_box(proj.progress, length).max(0.0);
+                limit.min((2.0 * PIT_DECELERATION * to_box).sqrt())
+            }
+            _ => limit,
+        };
+        let target = pit.lane_point(&self.path, proj.progress + PIT_LOOK_AHEAD).0;
+        (Some(target), speed)
+    }
+
+    /// Continues along the racing line from its point nearest ahead of the car.
+    fn resync(&mut self, pos: Vec2, dir: Vec2) {
+        let n = self.track_points.len();
+        let dist = |i: &usize| (self.track_points[*i] - pos).length();
+        let nearest = (0..n)
+            .min_by(|a, b| dist(a).partial_cmp(&dist(b)).unwrap())
+            .unwrap_or(0);
+        self.next_pt = if (self.track_points[nearest] - pos).dot(dir) < 0.0 {
+            (nearest + 1) % n
+        } else {
+            nearest
+        };
+    }
+
+    /// Heading change of the path over the next `distance` metres (rad).
+    fn turn_ahead(&self, distance: f32) -> f32 {
+        let n = self.track_points.len();
+        let pt = |k: usize| self.track_points[(self.next_pt + k) % n];
+        let mut turn = 0.0;
+        let mut length = 0.0;
+        let mut k = 0;
+        while length < distance && k < n {
+            let a = pt(k + 1) - pt(k);
+            let b = pt(k + 2) - pt(k + 1);
+            turn += (a.x * b.y - a.y * b.x).atan2(a.dot(b)).abs();
+            length += a.length();
+            k += 1;
+        }
+        turn
+    }
+
+    fn track_points(track_file: &str, lane: f32) -> Result<Vec<Vec2>, SerdeError> {
+        let data = Track::track_curve_control_points(track_file)?;
         let mut i = 0;
         let mut pts = vec![];
         let track_width = Track::track_width();
@@ -98,11 +392,47 @@ impl AiDriver {
 
             i += 3;
         }
-        Ok(AiDriver {
-            car: car_entity,
-            track_points: pts,
-            next_pt: 0,
-            target: AiTarget::new(),
-        })
+        Ok(pts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{Assists, TireConfig};
+
+    const LENGTH: f32 = 1000.0;
+
+    fn car() -> CarComponent {
+        CarComponent::test_car(TireConfig::default().model, Assists::default())
+    }
+
+    #[test]
+    fn a_fresh_car_does_not_stop() {
+        assert!(!needs_pit(&car(), None, 0.0, 5, LENGTH));
+    }
+
+    #[test]
+    fn stops_for_fuel_only_when_it_would_run_dry() {
+        let mut car = car();
+        car.fuel = 1.0;
+        assert!(needs_pit(&car, None, 0.0, 5, LENGTH));
+        assert!(!needs_pit(&car, None, 4.5 * LENGTH, 5, LENGTH));
+
+        // measured use beats the estimate once the car drove far enough
+        assert!(!needs_pit(&car, Some((0.0, 1.01)), 1000.0, 5, LENGTH));
+    }
+
+    #[test]
+    fn stops_for_tires_and_damage_unless_the_race_is_nearly_over() {
+        let mut worn = car();
+        worn.tires[1].wear = 0.8;
+        assert!(needs_pit(&worn, None, 0.0, 5, LENGTH));
+        assert!(!needs_pit(&worn, None, 3.5 * LENGTH, 5, LENGTH));
+
+        let mut wrecked = car();
+        wrecked.damage.left = 0.7;
+        assert!(needs_pit(&wrecked, None, 0.0, 5, LENGTH));
+        assert!(!needs_pit(&wrecked, None, 4.5 * LENGTH, 5, LENGTH));
     }
 }
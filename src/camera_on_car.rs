@@ -1,15 +1,120 @@
+use crate::camera_path::CameraPath;
 use crate::car::CarComponent;
+use crate::input::{Action, Controls};
+use crate::menu;
+use crate::race_control::NewSession;
+use crate::terrain::HeightMap;
+use crate::track::{LoadTrack, Track};
+use crate::tv_director::TvDirector;
+
+extern crate serde;
 
 use glui::mecs::*;
 use glui::tools::serde_tools::SerdeJsonQuick;
 use glui::tools::*;
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+const CLEARANCE_SAMPLES: [f32; 3] = [0.25, 0.5, 0.75]; // points between car and camera kept clear
+
+/// Placement and dynamics of the chase camera in one view.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct ChaseParams {
+    pub behind: f32,        // distance behind the car (m)
+    pub above: f32,         // height above the car (m)
+    pub right: f32,         // distance to the right of the car (m)
+    pub stiffness: f32,     // angular frequency of the position spring (1/s)
+    pub aim_stiffness: f32, // angular frequency of the aim spring (1/s)
+    pub lag: f32,           // the camera trails the car by this much of its velocity (s)
+    pub look_ahead: f32,    // the aim leads the car by this much of its velocity (s)
+    pub drift_offset: f32,  // sideways shift at a full sideways slide (m)
+    pub clearance: f32,     // minimal height above the terrain (m)
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub racer: ChaseParams,
+    pub side: ChaseParams,
+    pub top_down: ChaseParams,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        let racer = ChaseParams {
+            behind: 10.0,
+            above: 5.0,
+            right: 0.0,
+            stiffness: 6.0,
+            aim_stiffness: 12.0,
+            lag: 0.05,
+            look_ahead: 0.25,
+            drift_offset: 3.0,
+            clearance: 1.5,
+        };
+        CameraConfig {
+            racer,
+            side: ChaseParams {
+                behind: 0.0,
+                above: 2.0,
+                right: 7.0,
+                drift_offset: 0.0,
+                ..racer
+            },
+            top_down: ChaseParams {
+                behind: 0.5,
+                above: 17.0,
+                look_ahead: 0.0,
+                drift_offset: 0.0,
+                ..racer
+            },
+        }
+    }
+}
+
+impl CameraConfig {
+    pub fn load() -> CameraConfig {
+        match CameraConfig::load_json("camera.json") {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Failed to load camera config, using defaults: {:?}", e);
+                CameraConfig::default()
+            }
+        }
+    }
+
+    fn params(&self, view: View) -> ChaseParams {
+        match view {
+            View::Racer => self.racer,
+            View::Side => self.side,
+            View::TopDown | View::Tv => self.top_down,
+        }
+    }
+}
+
+/// Part of the window showing the view of the given local player, in columns like the HUDs.
+pub fn viewport(player: usize, players: usize) -> Rect {
+    let width = 1.0 / players as f32;
+    Rect::from_min_max(
+        Vec2::new(width * player as f32, 0.0),
+        Vec2::new(width * (player + 1) as f32, 1.0),
+    )
+}
+
+/// Critically damped spring step, exact for a target that holds still during the step.
+fn spring(pos: Vec3, vel: Vec3, target: Vec3, omega: f32, dt: f32) -> (Vec3, Vec3) {
+    let x = pos - target;
+    let temp = (vel + x * omega) * dt;
+    let decay = (-omega * dt).exp();
+    (target + (x + temp) * decay, (vel - temp * omega) * decay)
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum View {
     Racer,
     TopDown,
     Side,
+    Tv,
 }
 
 impl View {
@@ -17,84 +122,211 @@ impl View {
         match self {
             View::Racer => View::Side,
             View::Side => View::TopDown,
-            View::TopDown => View::Racer,
+            View::TopDown => View::Tv,
+            View::Tv => View::Racer,
         }
     }
 }
 
+/// What a camera starts out showing and the world it looks at.
+pub struct CameraSetup {
+    pub car: usize,     // index of the followed car, also the local player of the view
+    pub players: usize, // views sharing the window
+    pub view: View,
+    pub free_view: bool,
+    pub track_file: String,
+    pub heights: HeightMap,
+    pub controls: Controls,
+}
+
 pub struct CamFollowCar {
     cars: Vec<Entity>,
     active_car: usize,
+    player: usize,
+    players: usize,
     cam_entity: Entity,
     view: View,
     free_view: bool,
-    max_cam_speed: f32,
+    director: TvDirector,
+    base_fov: f32,
+    config: CameraConfig,
+    heights: HeightMap,
+    cam_vel: Vec3,
+    aim: Vec3,
+    aim_vel: Vec3,
+    path: CameraPath,
+    path_time: Option<f32>,
+    controls: Controls,
 }
 
 impl System for CamFollowCar {
+    fn receive(&mut self, msg: &Box<dyn Message>, _world: &mut StaticWorld) {
+        if let Some(LoadTrack(file)) = msg.downcast_ref::<LoadTrack>() {
+            match Track::centreline(file) {
+                Ok(path) => self.director = TvDirector::new(&path, self.cars.len(), self.base_fov),
+                Err(e) => println!("Failed to read path of {}: {:?}", file, e),
+            }
+        }
+        // the players drive in the new session, the TV of the demo gives way to their car
+        if msg.downcast_ref::<NewSession>().is_some() {
+            self.active_car = self.player;
+            if self.view == View::Tv {
+                self.view = View::Racer;
+            }
+        }
+    }
+
     fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) {
+            return;
+        }
         let dt = delta_time.as_secs_f32();
 
-        if !self.free_view {
-            let p = self.cam_should_be_pos(world);
-            let car = world
-                .component_mut::<CarComponent>(self.cars[self.active_car])
-                .unwrap();
-
-            let car_pos = car.pos3();
+        // follow another car once the followed one is taken off the track
+        if world
+            .component::<CarComponent>(self.cars[self.active_car])
+            .is_none()
+        {
+            self.active_car = self.next_car(world, 1);
+        }
 
+        if let Some(t) = self.path_time {
+            let t = t + dt;
             let cam = &mut world
                 .component_mut::<DataComponent<Camera>>(self.cam_entity)
                 .unwrap()
                 .data;
 
-            let cam_p = cam.params.spatial.pos;
-            let delta = p - cam_p;
-            let d = delta.length();
-            let step = (33.0 + d * 0.4 + d * d * 0.3).min(self.max_cam_speed) * dt;
+            match self.path.sample(t) {
+                Some((pos, target, fov)) if self.path.looped || t <= self.path.duration() => {
+                    self.path_time = Some(t);
+                    cam.params.fov = fov;
+                    cam.params.look_at(pos, target, Vec3::new(0.0, 1.0, 0.0));
+                    return;
+                }
+                _ => {
+                    self.path_time = None;
+                    cam.params.fov = self.base_fov;
+                }
+            }
+        }
 
-            let new_pos = if d < step || d < 0.001 {
-                p
-            } else {
-                let dir = delta.sgn();
-                cam_p + dir * step
-            };
+        if !self.free_view && self.view == View::Tv {
+            let shot = self.director.update(dt, &self.cars, world);
+            self.active_car = shot.car;
+
+            let cam = &mut world
+                .component_mut::<DataComponent<Camera>>(self.cam_entity)
+                .unwrap()
+                .data;
 
-            let t = cam.params.spatial.target;
-            let delta = car_pos - t;
-            let d = delta.length();
-            let new_target = if d < step || d < 0.001 {
-                car_pos
-            } else {
-                let dir = delta.sgn();
-                t + dir * step
+            cam.params.fov = shot.fov;
+            cam.params
+                .look_at(shot.pos, shot.target, Vec3::new(0.0, 1.0, 0.0));
+        } else if !self.free_view {
+            let car = match self.followed(world) {
+                Some(car) => car,
+                None => return,
             };
+            let params = self.config.params(self.view);
+            let p = self.cam_should_be_pos(&car);
+
+            let car_pos = car.pos3();
+            let aim_goal =
+                car_pos + Vec3::new(car.velocity.x, 0.0, car.velocity.y) * params.look_ahead;
 
-            self.max_cam_speed += 10000.0f32.max(self.max_cam_speed * 3.0).min(100.0) * dt;
+            let cam = &mut world
+                .component_mut::<DataComponent<Camera>>(self.cam_entity)
+                .unwrap()
+                .data;
+
+            let (mut new_pos, vel) = spring(
+                cam.params.spatial.pos,
+                self.cam_vel,
+                p,
+                params.stiffness,
+                dt,
+            );
+            self.cam_vel = vel;
+            let (aim, aim_vel) = spring(self.aim, self.aim_vel, aim_goal, params.aim_stiffness, dt);
+            self.aim = aim;
+            self.aim_vel = aim_vel;
+
+            // keep the camera above the dunes and the car in sight over them
+            let ground = self.heights.height_at(Vec2::new(new_pos.x, new_pos.z)) + params.clearance;
+            let mut min_y = ground;
+            for t in CLEARANCE_SAMPLES.iter() {
+                let q = car_pos + (new_pos - car_pos) * *t;
+                let h = self.heights.height_at(Vec2::new(q.x, q.z)) + params.clearance;
+                min_y = min_y.max(car_pos.y + (h - car_pos.y) / *t);
+            }
+            if new_pos.y < min_y {
+                new_pos.y = min_y;
+                self.cam_vel.y = self.cam_vel.y.max(0.0);
+            }
 
             cam.params
-                .look_at(new_pos, new_target, Vec3::new(0.0, 1.0, 0.0));
+                .look_at(new_pos, self.aim, Vec3::new(0.0, 1.0, 0.0));
         }
     }
     fn window_event(&mut self, event: &GlutinWindowEvent, world: &mut StaticWorld) -> bool {
-        if let GlutinWindowEvent::KeyboardInput { input, .. } = event {
-            let press = input.state == GlutinElementState::Pressed;
-            if let Some(key) = input.virtual_keycode {
-                if key == GlutinKey::T && !press {
-                    let cam = &mut world
-                        .component_mut::<DataComponent<Camera>>(self.cam_entity)
-                        .unwrap()
-                        .data;
+        // the camera keys are global, in split screen they drive the view of the first player
+        // and every view keeps following its own car
+        if self.player != 0 {
+            return false;
+        }
+        if let Some((action, press)) = self.controls.action(event, None) {
+            if action == Action::CycleView && !press {
+                let cam = &mut world
+                    .component_mut::<DataComponent<Camera>>(self.cam_entity)
+                    .unwrap()
+                    .data;
 
-                    if self.free_view {
-                        self.free_view = false;
-                        cam.set_controller(NoController {});
-                    } else {
-                        self.view = self.view.cycle();
-                    }
-                    self.max_cam_speed = 0.0;
+                if self.free_view {
+                    self.free_view = false;
+                    cam.set_controller(NoController {});
+                } else {
+                    self.view = self.view.cycle();
+                }
+                cam.params.fov = self.base_fov;
+            }
+            if action == Action::FreeView && !press {
+                let cam = &mut world
+                    .component_mut::<DataComponent<Camera>>(self.cam_entity)
+                    .unwrap()
+                    .data;
+
+                if self.free_view {
+                    self.free_view = false;
+                    cam.set_controller(NoController {});
+                } else {
+                    self.free_view = true;
+                    cam.params.fov = self.base_fov;
+                    let mut controller = ModelViewController::new(Vec2::new(1024.0, 768.0));
+                    *controller.spatial_mut() = cam.params.spatial;
+                    controller.disable_roll = true;
+                    cam.set_controller(controller);
                 }
-                if key == GlutinKey::Y && !press {
+            }
+            if action == Action::AddKeyframe && !press && self.free_view {
+                let cam = &world
+                    .component::<DataComponent<Camera>>(self.cam_entity)
+                    .unwrap()
+                    .data;
+
+                let spatial = cam.params.spatial;
+                self.path
+                    .add_keyframe(spatial.pos, spatial.target, cam.params.fov);
+                self.save_path();
+            }
+            if action == Action::RemoveKeyframe && !press && self.free_view {
+                self.path.keyframes.pop();
+                self.save_path();
+            }
+            if action == Action::PlayCameraPath && !press {
+                if self.path_time.is_some() {
+                    self.path_time = None;
+                } else if !self.path.keyframes.is_empty() {
                     let cam = &mut world
                         .component_mut::<DataComponent<Camera>>(self.cam_entity)
                         .unwrap()
@@ -103,24 +335,18 @@ impl System for CamFollowCar {
                     if self.free_view {
                         self.free_view = false;
                         cam.set_controller(NoController {});
-                    } else {
-                        self.free_view = true;
-                        let mut controller = ModelViewController::new(Vec2::new(1024.0, 768.0));
-                        *controller.spatial_mut() = cam.params.spatial;
-                        controller.disable_roll = true;
-                        cam.set_controller(controller);
                     }
-                    self.max_cam_speed = 0.0;
-                }
-                if key == GlutinKey::LBracket && !press {
-                    self.active_car = (self.active_car + 1) % self.cars.len();
-                    self.max_cam_speed = 0.0;
-                }
-                if key == GlutinKey::RBracket && !press {
-                    self.active_car = (self.active_car + self.cars.len() - 1) % self.cars.len();
-                    self.max_cam_speed = 0.0;
+                    self.path_time = Some(0.0);
                 }
             }
+            if action == Action::NextCar && !press && self.players <= 1 {
+                self.active_car = self.next_car(world, 1);
+                self.director.hold(self.active_car);
+            }
+            if action == Action::PreviousCar && !press && self.players <= 1 {
+                self.active_car = self.next_car(world, self.cars.len() - 1);
+                self.director.hold(self.active_car);
+            }
         }
 
         false
@@ -128,34 +354,46 @@ impl System for CamFollowCar {
 }
 
 impl CamFollowCar {
-    fn cam_should_be_pos(&self, world: &mut StaticWorld) -> Vec3 {
-        let car = world
-            .component_mut::<CarComponent>(self.cars[self.active_car])
-            .unwrap();
-
-        let mut p = car.pos3();
-        let r = car.right3();
-        match self.view {
-            View::Racer => {
-                p += Vec3::new(0.0, 5.0, 0.0) - car.dir3() * 10.0;
-            }
-            View::Side => {
-                p += Vec3::new(0.0, 2.0, 0.0) + r * 7.0;
-            }
-            View::TopDown => {
-                p += Vec3::new(0.0, 17.0, 0.0) - car.dir3() * 0.5;
-            }
-        }
+    // the first car still on the track the given number of places after the followed one
+    fn next_car(&self, world: &StaticWorld, step: usize) -> usize {
+        let n = self.cars.len();
+        (1..=n)
+            .map(|k| (self.active_car + k * step) % n)
+            .find(|i| world.component::<CarComponent>(self.cars[*i]).is_some())
+            .unwrap_or(self.active_car)
+    }
 
-        p
+    fn followed(&self, world: &StaticWorld) -> Option<CarComponent> {
+        world
+            .component::<CarComponent>(self.cars[self.active_car])
+            .copied()
     }
-    fn init_cam(&self, world: &mut StaticWorld) {
-        let car = world
-            .component_mut::<CarComponent>(self.cars[self.active_car])
-            .unwrap();
+    fn cam_should_be_pos(&self, car: &CarComponent) -> Vec3 {
+        let params = self.config.params(self.view);
+        let speed = car.speed();
+        let slide = if speed > 1.0 {
+            car.velocity.dot(car.right()) / speed
+        } else {
+            0.0
+        };
 
+        car.pos3() + Vec3::new(0.0, params.above, 0.0) - car.dir3() * params.behind
+            + car.right3() * (params.right - slide * params.drift_offset)
+            - Vec3::new(car.velocity.x, 0.0, car.velocity.y) * params.lag
+    }
+    fn save_path(&self) {
+        match self.path.save() {
+            Ok(()) => println!("Camera path has {} keyframe(s)", self.path.keyframes.len()),
+            Err(e) => println!("Failed to save camera path: {:?}", e),
+        }
+    }
+    fn init_cam(&mut self, world: &mut StaticWorld) {
+        let car = match self.followed(world) {
+            Some(car) => car,
+            None => return,
+        };
         let car_p = car.pos3();
-        let p = self.cam_should_be_pos(world) + Vec3::new(0.0, 5.0, 0.0);
+        let p = self.cam_should_be_pos(&car) + Vec3::new(0.0, 5.0, 0.0);
 
         let cam = &mut world
             .component_mut::<DataComponent<Camera>>(self.cam_entity)
@@ -172,20 +410,49 @@ impl CamFollowCar {
         } else {
             cam.params.look_at(p, car_p, Vec3::new(0.0, 1.0, 0.0));
         }
+        self.aim = car_p;
     }
     pub fn new(
         cars: Vec<Entity>,
         camera: Entity,
-        free_view: bool,
+        setup: CameraSetup,
         world: &mut StaticWorld,
     ) -> CamFollowCar {
-        let me = CamFollowCar {
+        let CameraSetup {
+            car,
+            players,
+            view,
+            free_view,
+            track_file,
+            heights,
+            controls,
+        } = setup;
+        let base_fov = world
+            .component::<DataComponent<Camera>>(camera)
+            .unwrap()
+            .data
+            .params
+            .fov;
+        let path = Track::centreline(&track_file).expect("Failed to read track!");
+        let director = TvDirector::new(&path, cars.len(), base_fov);
+        let mut me = CamFollowCar {
             cars,
-            active_car: 0,
+            active_car: car,
+            player: car,
+            players,
             cam_entity: camera,
-            view: View::Racer,
+            view,
             free_view,
-            max_cam_speed: 0.0,
+            director,
+            base_fov,
+            config: CameraConfig::load(),
+            heights,
+            cam_vel: Vec3::zero(),
+            aim: Vec3::zero(),
+            aim_vel: Vec3::zero(),
+            path: CameraPath::load(),
+            path_time: None,
+            controls,
         };
         me.init_cam(world);
         me
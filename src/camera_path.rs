@@ -0,0 +1,106 @@
+extern crate serde;
+
+use glui::tools::serde_tools::{SerdeError, SerdeJsonQuick};
+use glui::tools::Vec3;
+use serde::{Deserialize, Serialize};
+
+pub const CAMERA_PATH_FILE: &str = "cam_path.json";
+const KEYFRAME_GAP: f32 = 4.0; // time between a new keyframe and the previous one (s)
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub pos: Vec3,
+    pub target: Vec3,
+    pub fov: f32,  // rad
+    pub time: f32, // since the start of the path (s)
+}
+
+/// Scripted camera movement through keyframes, interpolated by Catmull-Rom splines.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    pub keyframes: Vec<Keyframe>,
+    pub looped: bool,
+}
+
+impl CameraPath {
+    pub fn load() -> CameraPath {
+        CameraPath::load_json(CAMERA_PATH_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), SerdeError> {
+        self.save_json(CAMERA_PATH_FILE)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0.0)
+    }
+
+    pub fn add_keyframe(&mut self, pos: Vec3, target: Vec3, fov: f32) {
+        let time = self
+            .keyframes
+            .last()
+            .map(|k| k.time + KEYFRAME_GAP)
+            .unwrap_or(0.0);
+        self.keyframes.push(Keyframe {
+            pos,
+            target,
+            fov,
+            time,
+        });
+    }
+
+    /// Camera position, target and field of view at the given time, None without keyframes.
+    pub fn sample(&self, time: f32) -> Option<(Vec3, Vec3, f32)> {
+        let n = self.keyframes.len();
+        if n == 0 {
+            return None;
+        }
+        let time = if self.looped && self.duration() > 0.0 {
+            time.rem_euclid(self.duration())
+        } else {
+            time.max(0.0).min(self.duration())
+        };
+
+        let mut i = 0;
+        while i + 2 < n && self.keyframes[i + 1].time <= time {
+            i += 1;
+        }
+        if n == 1 {
+            let k = self.keyframes[0];
+            return Some((k.pos, k.target, k.fov));
+        }
+
+        let k1 = self.keyframes[i];
+        let k2 = self.keyframes[i + 1];
+        let k0 = if i > 0 { self.keyframes[i - 1] } else { k1 };
+        let k3 = if i + 2 < n { self.keyframes[i + 2] } else { k2 };
+        let span = k2.time - k1.time;
+        let t = if span > 0.0 {
+            ((time - k1.time) / span).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        Some((
+            catmull_rom(k0.pos, k1.pos, k2.pos, k3.pos, t),
+            catmull_rom(k0.target, k1.target, k2.target, k3.target, t),
+            catmull_rom(k0.fov, k1.fov, k2.fov, k3.fov, t),
+        ))
+    }
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
@@ -1,17 +1,45 @@
 extern crate rand_distr;
+extern crate serde;
 
 #[allow(deprecated)]
 use self::rand_distr::{Distribution, Normal};
 // use crate::line_system::{DelLine, LineDesc, LineSystem, LinesUpdate, SetLine};
+use crate::menu;
+use crate::race_control::NewSession;
+use crate::terrain::HeightMap;
+use crate::tire_model::{self, TireModel};
 use glui::graphics::{DrawShaderSelector, RenderCommand, RenderSequence};
-use glui::mecs::{BodyComponent, Component, DrawComponent, Entity, StaticWorld, System};
+use glui::mecs::{BodyComponent, Component, DrawComponent, Entity, Message, StaticWorld, System};
 use glui::tools::mesh::{Mesh, MeshOnGPU};
+use glui::tools::serde_tools::SerdeJsonQuick;
 use glui::tools::{
     Buffer, DrawMode, Mat4, Rect, RgbaTexture, Uniform, Vec2, Vec3, Vec4, VertexArray,
 };
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
 use std::f32::consts::PI;
 use std::time::Duration;
 
+const PAINT_MESH: usize = 2; // mesh of the body painted in the colour of the car
+const RESTITUTION: f32 = 0.3; // share of the closing speed kept after an impact
+const ZONE_STRENGTH: f32 = 150000.0; // impact energy that wrecks a zone of the body (J)
+const DAMAGE_POWER_LOSS: f32 = 0.5; // engine force lost with a wrecked front (ratio)
+const DAMAGE_MISALIGNMENT: f32 = 0.05; // steering skew of a wrecked side (rad)
+const DAMAGE_SOFTENING: f32 = 0.6; // suspension stiffness lost with a wrecked end (ratio)
+const DAMAGE_DARKENING: f32 = 0.6; // paint darkened on a wrecked car (ratio)
+const WHEEL_DROOP: f32 = 0.08; // spring extension beyond rest before a wheel leaves the road (m)
+const WHEEL_INERTIA: f32 = 2.0; // inertia of the wheels of an axle with what turns them (kg m^2)
+const BRAKE_BIAS: f32 = 0.6; // share of the braking force on the front wheels
+const REDLINE_SPEED: f32 = 100.0; // tread speed where the engine stops spinning the wheels (m/s)
+const SUSPENSION_TRAVEL: f32 = 0.4; // compression of a spring before it hits its bump stop (m)
+const ATTITUDE_LAG: f32 = 0.1; // smoothing of the pitch and roll rates taken from the ground (s)
+const WAKE_SPEED: f32 = 14.0; // speed of the leader from which its wake is fully formed (m/s)
+const SLIDE_FADE_START: f32 = 10.0; // speed from which slides count as drifts and for ESC (m/s)
+const SLIDE_FADE_END: f32 = 16.0; // speed from which they fully count (m/s)
+const STANDSTILL_SPEED: f32 = 0.5; // speed below which a car without throttle is held (m/s)
+const STANDSTILL_DECELERATION: f32 = 6.0; // deceleration holding it when stopped (m/s^2)
+
 struct CarBody {
     meshes: Vec<MeshOnGPU>,
 }
@@ -20,145 +48,47 @@ pub struct CarSystem {
     body_mesh: CarBody,
     wheel_mesh: MeshOnGPU,
     shadow: RgbaTexture,
+    damage: bool, // impacts damage the cars
+    heights: Option<HeightMap>,
 }
 
 impl System for CarSystem {
     fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) {
+            return;
+        }
         let dt = delta_time.as_secs_f32();
 
         let car_entities = world.entities_having_component::<CarComponent>();
 
+        // strongest wake each car drives in
+        let cars = car_entities
+            .iter()
+            .map(|e| *world.component::<CarComponent>(*e).unwrap())
+            .collect::<Vec<_>>();
+        let drafts = cars
+            .iter()
+            .enumerate()
+            .map(|(i, car)| {
+                cars.iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, leader)| car.draft_behind(leader))
+                    .fold(0.0, f32::max)
+            })
+            .collect::<Vec<_>>();
+
         for i in 0..car_entities.len() {
             let body_entity = car_entities[i];
             let mut car_clone = None;
 
             if let Some(car) = world.component_mut::<CarComponent>(body_entity) {
-                // println!(
-                //     "throttle: {}, brake: {}, steer: {}",
-                //     car.throttle, car.brake, car.steer
-                // );
-
-                // init config
-                let cfg = &car.config;
-                let inertia = cfg.mass * cfg.inertia_ratio;
-                let wheel_base = cfg.wheel_base();
-                let axle_weight_ratio_front = cfg.cg_to_rear_axle / wheel_base;
-                let axle_weight_ratio_rear = cfg.cg_to_front_axle / wheel_base;
-                let steer_angle = cfg.max_steer * car.steer;
-                let steer_angle = steer_angle / (1.0 + car.speed() / 60.0);
-
-                // local base
-                let dir = car.dir();
-                let right = car.right();
-
-                // velocity and acceleration in local coordinates
-                let vel_long = car.velocity.dot(dir);
-                let vel_lat = car.velocity.dot(right);
-                let acc_long = car.acceleration.dot(dir);
-
-                // weight on axles
-                let axle_weight_front = cfg.mass
-                    * (axle_weight_ratio_front * cfg.gravity
-                        - cfg.weight_transfer * acc_long * cfg.cg_height / wheel_base);
-                let axle_weight_rear = cfg.mass
-                    * (axle_weight_ratio_rear * cfg.gravity
-                        + cfg.weight_transfer * acc_long * cfg.cg_height / wheel_base);
-
-                // velocity of wheels from angular velocity
-                let vel_from_angular_front = cfg.cg_to_front_axle * car.angular_velocity;
-                let vel_from_angular_rear = -cfg.cg_to_rear_axle * car.angular_velocity;
-
-                // slip angles
-                let mut slip_angle_front = (vel_lat + vel_from_angular_front).atan2(vel_long.abs())
-                    - vel_long.signum() * steer_angle;
-                let mut slip_angle_rear = (vel_lat + vel_from_angular_rear).atan2(vel_long.abs());
-
-                // print!("{}, {} -> ", slip_angle_front, slip_angle_rear);
-                if slip_angle_front > PI / 2.0 {
-                    slip_angle_front = PI - slip_angle_front;
-                }
-                if slip_angle_rear > PI / 2.0 {
-                    slip_angle_rear = PI - slip_angle_rear;
-                }
-                if slip_angle_front < -PI / 2.0 {
-                    slip_angle_front = -PI - slip_angle_front;
+                car.draft = drafts[i];
+                if let Some(heights) = &self.heights {
+                    car.terrain_height = heights.height_at(car.position);
+                    car.terrain_slope = heights.slope_at(car.position);
                 }
-                if slip_angle_rear < -PI / 2.0 {
-                    slip_angle_rear = -PI - slip_angle_rear;
-                }
-                // println!("{}, {}", slip_angle_front, slip_angle_rear);
-
-                let tire_grip_front = cfg.tire_grip;
-                let tire_grip_rear = cfg.tire_grip; // todo add support for hand break
-
-                let f_friction_front = (-cfg.corner_stiffness_front * slip_angle_front)
-                    .clamp(-tire_grip_front, tire_grip_front)
-                    * axle_weight_front;
-                let f_friction_rear = (-cfg.corner_stiffness_rear * slip_angle_rear)
-                    .clamp(-tire_grip_rear, tire_grip_rear)
-                    * axle_weight_rear;
-
-                // brake and throttle forces
-                let f_brake = car.brake * cfg.brake_force; // todo hand break
-                let f_throttle = car.throttle * cfg.engine_force;
-
-                // brake and throttle local force
-                let f_traction = f_throttle - f_brake * vel_long.signum();
-
-                let f_drag_long = -cfg.roll_resistance * vel_long
-                    - cfg.air_resistance * vel_long * vel_long.abs();
-                let f_drag_lat =
-                    -cfg.roll_resistance * vel_lat - cfg.air_resistance * vel_lat * vel_lat.abs();
-
-                // total local force
-                let f_tot_long = f_drag_long - steer_angle.sin() * f_friction_front + f_traction;
-                let f_tot_lat = f_drag_lat + steer_angle.cos() * f_friction_front + f_friction_rear;
-
-                // local acceleration
-                let a_long = f_tot_long / cfg.mass;
-                let a_lat = f_tot_lat / cfg.mass;
-
-                // acceleration in world coordinates
-                let a = a_long * dir + a_lat * right;
-
-                car.front_susp.apply_force(axle_weight_front, dt, cfg.mass);
-                car.rear_susp.apply_force(axle_weight_rear, dt, cfg.mass);
-
-                if car.speed() < 14.0 {
-                    car.angular_velocity = car.speed() / cfg.wheel_base() * steer_angle.sin();
-
-                    let a = (f_drag_long + f_traction) / cfg.mass;
-
-                    car.acceleration = a * dir;
-                    car.velocity = (car.speed() + a * dt) * dir;
-
-                    if car.speed() < 0.2 && car.throttle == 0.0 {
-                        car.velocity = Vec2::zero();
-                        // car.acceleration = Vec2::zero();
-                    }
-
-                    car.heading += car.angular_velocity * dt;
-
-                    car.position += car.velocity * dt;
-                } else {
-                    car.acceleration = a;
-                    car.velocity += car.acceleration * dt;
-
-                    // rotational forces
-                    let body_torque = f_friction_front * cfg.cg_to_front_axle
-                        - f_friction_rear * cfg.cg_to_rear_axle;
-
-                    let angular_acceleration = body_torque / inertia;
-
-                    car.angular_velocity += angular_acceleration * dt;
-                    car.heading += car.angular_velocity * dt;
-
-                    car.position += car.velocity * dt;
-                }
-
-                car.wheel_roll += car.speed() / cfg.wheel_radius * dt;
-
-                car.terrain_height = 0.0;
+                car.step(dt);
 
                 car_clone = Some(car.clone());
             }
@@ -249,23 +179,47 @@ impl System for CarSystem {
 
             // draw update
             if let Some(car) = car_clone {
-                let car_h =
-                    (car.front_susp.length + car.rear_susp.length) / 2.0 + car.config.wheel_radius;
-                let car_pitch =
-                    (car.front_susp.length - car.rear_susp.length).atan2(car.config.wheel_base());
+                let susp = car.suspension.map(|s| s.length);
+                let car_h = susp.iter().sum::<f32>() / 4.0 + car.config.wheel_radius;
+                let car_pitch = ((susp[0] + susp[1]) / 2.0 - (susp[2] + susp[3]) / 2.0)
+                    .atan2(car.config.wheel_base());
+                let car_roll = ((susp[0] + susp[3]) / 2.0 - (susp[1] + susp[2]) / 2.0)
+                    .atan2(car.config.track_width());
 
                 let ori_scale =
                     Mat4::rotate_y(-car.heading) * Mat4::scale3(car.config.body_size() / 2.0);
 
-                let ori_scale_bob = Mat4::rotate_y(-car.heading)
-                    * Mat4::rotate_z(car_pitch)
-                    * Mat4::scale3(car.config.body_size() / 2.0);
-
-                let body_draw = world.component_mut::<DrawComponent>(body_entity).unwrap();
-
-                body_draw.model_matrix = Mat4::offset(car.pos3() + Vec3::new(0.0, car_h, 0.0))
-                    * ori_scale_bob
-                    * Mat4::offset(Vec3::new(0.0, 1.0, 0.0));
+                let body_ori = Mat4::rotate_y(-car.heading)
+                    * Mat4::rotate_z(car.pitch + car_pitch)
+                    * Mat4::rotate_x(car.roll + car_roll);
+                let body_pos = car.pos3() + Vec3::new(0.0, car_h, 0.0);
+
+                let ori_scale_bob = body_ori * Mat4::scale3(car.config.body_size() / 2.0);
+
+                // a car put back on the track is drawn once its parts are restored
+                let body_draw = match world.component_mut::<DrawComponent>(body_entity) {
+                    Some(draw) => draw,
+                    None => continue,
+                };
+
+                body_draw.model_matrix =
+                    Mat4::offset(body_pos) * ori_scale_bob * Mat4::offset(Vec3::new(0.0, 1.0, 0.0));
+
+                // dents darken the paint
+                let paint = car.color.rgb() * (1.0 - DAMAGE_DARKENING * car.damage.total());
+                if let Some(cmd) = body_draw.render_seq.command_iter_mut().nth(PAINT_MESH) {
+                    for uniform in cmd.uniforms.iter_mut() {
+                        if let Uniform::Vector3(name, _) = uniform {
+                            let c = match name.as_str() {
+                                "Ka" => paint / 4.0,
+                                "Kd" => paint,
+                                "Ks" => paint * 0.3,
+                                _ => continue,
+                            };
+                            *uniform = Uniform::from(name, c);
+                        }
+                    }
+                }
 
                 let mut light_dir = Vec3::zero();
 
@@ -273,16 +227,18 @@ impl System for CarSystem {
                     light_dir = dir * Vec3::new(1.0, 0.0, 1.0);
                 }
 
-                let shadow_draw = world.component_mut::<DrawComponent>(car.shadow).unwrap();
+                let parts = *world.component::<CarParts>(body_entity).unwrap();
+                let shadow_draw = world.component_mut::<DrawComponent>(parts.shadow).unwrap();
 
                 let depth = 0.02 + i as f32 * 0.0035;
-                let center = car.pos3() + Vec3::new(0.0, depth, 0.0);
+                let ground = Vec3::new(car.position.x, car.terrain_height, car.position.y);
+                let center = ground + Vec3::new(0.0, depth, 0.0);
                 shadow_draw.model_matrix = Mat4::offset(center - light_dir * 0.1) * ori_scale;
 
-                let shadow_body = world.component_mut::<BodyComponent>(car.shadow).unwrap();
+                let shadow_body = world.component_mut::<BodyComponent>(parts.shadow).unwrap();
                 shadow_body.center.z = -(i as f32);
 
-                let wheel_dist = car.config.body_size().z / 2.0 - car.config.wheel_width;
+                let wheel_dist = car.config.track_width() / 2.0;
 
                 for (x, z, i) in [
                     (car.config.cg_to_rear_axle, -wheel_dist, 0),
@@ -294,7 +250,7 @@ impl System for CarSystem {
                 .copied()
                 {
                     let wheel_draw = world
-                        .component_mut::<DrawComponent>(car.wheels[i as usize])
+                        .component_mut::<DrawComponent>(parts.wheels[i as usize])
                         .unwrap();
 
                     let turn_angle = if i == 0 || i == 1 {
@@ -303,9 +259,13 @@ impl System for CarSystem {
                         0.0
                     } + if i == 1 || i == 2 { PI } else { 0.0 };
 
-                    wheel_draw.model_matrix = Mat4::offset(car.pos3())
-                        * Mat4::rotate_y(-car.heading)
-                        * Mat4::offset(Vec3::new(x, 0.0, z))
+                    // the wheel hangs its spring below the body, along the up axis of the body,
+                    // rising as the spring compresses and dropping as it stretches
+                    let hang = car.suspension[i as usize].length + car.config.wheel_radius;
+
+                    wheel_draw.model_matrix = Mat4::offset(body_pos)
+                        * body_ori
+                        * Mat4::offset(Vec3::new(x, -hang, z))
                         * Mat4::rotate_y(-turn_angle)
                         * Mat4::scale3(Vec3::new(
                             car.config.wheel_radius,
@@ -318,6 +278,21 @@ impl System for CarSystem {
             }
         }
         // world.send_by_type::<LineSystem, _>(LinesUpdate {});
+
+        self.collide(&car_entities, world);
+    }
+
+    fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
+        if let Some(RestoreCar(e)) = msg.downcast_ref::<RestoreCar>() {
+            if let Some(color) = world.component::<CarComponent>(*e).map(|c| c.color) {
+                if world.component::<CarParts>(*e).is_none() {
+                    self.add_parts(world, *e, color);
+                }
+            }
+        }
+        if let Some(session) = msg.downcast_ref::<NewSession>() {
+            self.damage = session.damage;
+        }
     }
 }
 //
@@ -350,11 +325,45 @@ impl System for CarSystem {
 // }
 
 impl CarSystem {
-    pub fn new() -> CarSystem {
+    pub fn new(damage: bool) -> CarSystem {
         CarSystem {
             body_mesh: Self::load_body(),
             wheel_mesh: Self::load_wheel(),
             shadow: RgbaTexture::from_file("images/shadow.png").unwrap_or(RgbaTexture::unit()),
+            damage,
+            heights: None,
+        }
+    }
+
+    /// Lets the cars drive over the terrain and jump off its dunes.
+    pub fn with_terrain(mut self, heights: HeightMap) -> CarSystem {
+        self.heights = Some(heights);
+        self
+    }
+
+    /// Pushes overlapping cars apart, exchanging momentum and turning the energy of the impact
+    /// into damage of the zones hit.
+    fn collide(&self, cars: &[Entity], world: &mut StaticWorld) {
+        let mut states = cars
+            .iter()
+            .map(|e| *world.component::<CarComponent>(*e).unwrap())
+            .collect::<Vec<_>>();
+        let mut touched = vec![false; states.len()];
+
+        for i in 0..states.len() {
+            for j in i + 1..states.len() {
+                let (first, rest) = states.split_at_mut(j);
+                if first[i].impact(&mut rest[0], self.damage) {
+                    touched[i] = true;
+                    touched[j] = true;
+                }
+            }
+        }
+
+        for (k, e) in cars.iter().enumerate() {
+            if touched[k] {
+                *world.component_mut::<CarComponent>(*e).unwrap() = states[k];
+            }
         }
     }
 
@@ -412,8 +421,8 @@ impl CarSystem {
             primary_color,
         ];
         let ns = vec![
-            9.0,   // handle
-            30.0,  // ?
+            9.0,  // handle
+            30.0, // ?
             30.0, // body
         ];
 
@@ -463,14 +472,21 @@ impl CarSystem {
         rs
     }
 
-    pub fn create_car(
+    pub fn create_car<R: Rng>(
         &self,
         world: &mut StaticWorld,
         init_state: (f32, Vec2),
-        color: Vec4,
-        randomness: f32,
+        spec: CarSpec,
+        rng: &mut R,
     ) -> Entity {
         let e = world.entity();
+        self.add_parts(world, e, spec.color);
+        world.add_component(e, CarComponent::new_stiff(init_state, spec, rng));
+        e
+    }
+
+    // the body, wheels and shadow drawing the car
+    fn add_parts(&self, world: &mut StaticWorld, e: Entity, color: Vec4) {
         world.add_component(
             e,
             DrawComponent::from_render_seq(self.all_body_render_seq(color)),
@@ -494,12 +510,34 @@ impl CarSystem {
         );
         world.add_component(
             e,
-            CarComponent::new_stiff(wheels, shadow_entity, init_state, randomness),
+            CarParts {
+                wheels,
+                shadow: shadow_entity,
+            },
         );
-        e
+    }
+
+    /// Despawns the car with the entities of its wheels and shadow. The entity of the car is
+    /// left empty rather than deleted, so that the systems holding it see the car gone and find
+    /// it again once it is restored.
+    pub fn remove_car(world: &mut StaticWorld, e: Entity) {
+        if let Some(parts) = world.component::<CarParts>(e).copied() {
+            for wheel in parts.wheels.iter().copied() {
+                world.delete_entity(wheel);
+            }
+            world.delete_entity(parts.shadow);
+        }
+        world.remove_component::<CarParts>(e);
+        world.remove_component::<DrawComponent>(e);
+        world.remove_component::<CarComponent>(e);
     }
 }
 
+/// How much a slide at the given speed counts, from none when slow to fully when fast.
+fn slide_weight(speed: f32) -> f32 {
+    ((speed - SLIDE_FADE_START) / (SLIDE_FADE_END - SLIDE_FADE_START)).clamp(0.0, 1.0)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Suspension {
     pub eq_length: f32,
@@ -507,6 +545,7 @@ pub struct Suspension {
     pub velocity: f32,
     pub dampening: f32,
     pub stiffness: f32,
+    pub damage: f32, // softens the spring and the damper, in [0,1]
 }
 
 impl Suspension {
@@ -515,6 +554,7 @@ impl Suspension {
             eq_length,
             dampening,
             stiffness,
+            damage: 0.0,
             velocity: 0.0,
             length: eq_length,
         }
@@ -522,15 +562,349 @@ impl Suspension {
 
     pub fn apply_force(&mut self, force: f32, time: f32, mass: f32) {
         let dx = self.eq_length - self.length;
-        let net_force = -force + dx * self.stiffness - self.velocity * self.dampening;
+        let softness = 1.0 - DAMAGE_SOFTENING * self.damage;
+        let net_force = -force + (dx * self.stiffness - self.velocity * self.dampening) * softness;
         let acceleration = net_force / mass;
 
         self.velocity += acceleration * time;
         self.length += self.velocity * time;
+
+        // the bump stop takes what the spring could not
+        if self.length < self.eq_length - SUSPENSION_TRAVEL {
+            self.length = self.eq_length - SUSPENSION_TRAVEL;
+            self.velocity = self.velocity.max(0.0);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Zone {
+    Front,
+    Rear,
+    Left,
+    Right,
+}
+
+/// Damage of the zones of the body, each in [0,1].
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Damage {
+    pub front: f32,
+    pub rear: f32,
+    pub left: f32,
+    pub right: f32,
+}
+
+impl Damage {
+    pub fn hit(&mut self, zone: Zone, amount: f32) {
+        let z = match zone {
+            Zone::Front => &mut self.front,
+            Zone::Rear => &mut self.rear,
+            Zone::Left => &mut self.left,
+            Zone::Right => &mut self.right,
+        };
+        *z = (*z + amount).min(1.0);
+    }
+
+    pub fn worst(&self) -> f32 {
+        self.front.max(self.rear).max(self.left).max(self.right)
     }
+
+    pub fn total(&self) -> f32 {
+        (self.front + self.rear + self.left + self.right) / 4.0
+    }
+}
+
+/// Driver aids of a car, each of them can be switched off.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Assists {
+    pub abs: bool,
+    pub traction_control: bool,
+    pub stability_control: bool,
+    // brakes lock the wheels and traction uses up grip of the rear tires, the limits ABS and
+    // traction control keep the car within
+    pub grip_limits: bool,
+    pub abs_slip: f32, // share of the locking brake force ABS lets through (ratio)
+    pub tc_slip_angle: f32, // rear slip angle allowed on throttle (rad)
+    pub esc_yaw_rate: f32, // yaw rate tolerated beyond the steered one (rad/s)
+    pub esc_strength: f32, // how fast the excess yaw rate is braked away (1/s)
+}
+
+impl Default for Assists {
+    fn default() -> Self {
+        Assists {
+            abs: false,
+            traction_control: false,
+            stability_control: false,
+            grip_limits: false,
+            abs_slip: 0.9,
+            tc_slip_angle: 0.15,
+            esc_yaw_rate: 0.3,
+            esc_strength: 4.0,
+        }
+    }
+}
+
+/// A car to race with, named after the aids it is fitted with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarPreset {
+    pub name: String,
+    pub assists: Assists,
 }
 
+impl Default for CarPreset {
+    fn default() -> Self {
+        CarPreset {
+            name: "Stock".to_owned(),
+            assists: Assists::default(),
+        }
+    }
+}
+
+/// The cars of `cars.json`, the players pick one of them and the AI cars take them in turn.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CarPresets {
+    pub cars: Vec<CarPreset>,
+    pub player: usize, // preset driven by the players unless they pick another one
+}
+
+impl Default for CarPresets {
+    fn default() -> Self {
+        let all = Assists {
+            abs: true,
+            traction_control: true,
+            stability_control: true,
+            grip_limits: true,
+            ..Default::default()
+        };
+        CarPresets {
+            cars: vec![
+                CarPreset {
+                    name: "Road".to_owned(),
+                    assists: all,
+                },
+                CarPreset {
+                    name: "Sport".to_owned(),
+                    assists: Assists {
+                        stability_control: false,
+                        ..all
+                    },
+                },
+                CarPreset::default(),
+            ],
+            player: 1,
+        }
+    }
+}
+
+impl CarPresets {
+    pub fn load() -> CarPresets {
+        match CarPresets::load_json("cars.json") {
+            Ok(presets) if !presets.cars.is_empty() => presets,
+            Ok(_) => {
+                println!("No cars in cars.json, using defaults");
+                CarPresets::default()
+            }
+            Err(e) => {
+                println!("Failed to load cars, using defaults: {:?}", e);
+                CarPresets::default()
+            }
+        }
+    }
+
+    /// The preset of the given number, counting around the list.
+    pub fn get(&self, i: usize) -> &CarPreset {
+        &self.cars[i % self.cars.len()]
+    }
+}
+
+/// What a new car is built from.
 #[derive(Debug, Copy, Clone)]
+pub struct CarSpec {
+    pub color: Vec4,
+    pub assists: Assists,
+    pub tires: TireConfig,
+    pub randomness: f32, // standard deviation of the parameters around the base car (ratio)
+}
+
+/// Nitro tank of a car, how fast it is used up and refilled.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoostConfig {
+    pub capacity: f32,       // nitro in a full tank
+    pub burn_rate: f32,      // nitro used per second of boost
+    pub recharge_rate: f32,  // nitro gained per second when not boosting
+    pub drift_recharge: f32, // nitro gained per second and radian of rear slip while drifting
+    pub force: f32,          // extra force of the engine while boosting (N)
+}
+
+impl Default for BoostConfig {
+    fn default() -> Self {
+        BoostConfig {
+            capacity: 1.0,
+            burn_rate: 0.25,
+            recharge_rate: 0.02,
+            drift_recharge: 0.5,
+            force: 6000.0,
+        }
+    }
+}
+
+/// How the tires heat up, cool down and wear, and how that changes their grip.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TireConfig {
+    pub ambient: f32,          // temperature of the air and of fresh tires (°C)
+    pub optimal: f32,          // middle of the temperature window with full grip (°C)
+    pub window: f32,           // half width of the window with full grip (°C)
+    pub falloff: f32,          // grip lost per degree outside the window (ratio)
+    pub min_grip: f32,         // grip of tires far too cold or hot (ratio)
+    pub heating: f32,          // warming per kW of slip power (°C/s)
+    pub cooling: f32,          // share of the heat above ambient lost per second and m/s of speed
+    pub wear_rate: f32,        // tread worn per MJ of slip energy (ratio)
+    pub worn_grip: f32,        // grip of fully worn tires (ratio)
+    pub load_sensitivity: f32, // grip lost per share of load above the static load (ratio)
+    pub model: TireModel,
+}
+
+impl Default for TireConfig {
+    fn default() -> Self {
+        TireConfig {
+            ambient: 25.0,
+            optimal: 80.0,
+            window: 20.0,
+            falloff: 0.005,
+            min_grip: 0.6,
+            heating: 0.1,
+            cooling: 0.0008,
+            wear_rate: 0.025,
+            worn_grip: 0.6,
+            load_sensitivity: 0.1,
+            model: TireModel::default(),
+        }
+    }
+}
+
+impl TireConfig {
+    pub fn load() -> TireConfig {
+        match TireConfig::load_json("tires.json") {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Failed to load tires, using defaults: {:?}", e);
+                TireConfig::default()
+            }
+        }
+    }
+}
+
+/// Fuel tank of a car, the fuel adds to the mass of the car.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FuelConfig {
+    pub capacity: f32,  // fuel in a full tank (kg)
+    pub burn_rate: f32, // fuel used per second at full throttle (kg/s)
+}
+
+impl Default for FuelConfig {
+    fn default() -> Self {
+        FuelConfig {
+            capacity: 60.0,
+            burn_rate: 0.05,
+        }
+    }
+}
+
+/// How weight moves across the car in corners and the anti-roll bars resisting the roll.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RollConfig {
+    pub load_transfer: f32,   // how much weight is transferred in corners (ratio)
+    pub anti_roll_front: f32, // stiffness of the front anti-roll bar (N/m)
+    pub anti_roll_rear: f32,  // stiffness of the rear anti-roll bar (N/m)
+}
+
+impl Default for RollConfig {
+    fn default() -> Self {
+        RollConfig {
+            load_transfer: 1.0,
+            anti_roll_front: 15000.0,
+            anti_roll_rear: 8000.0,
+        }
+    }
+}
+
+/// Downforce of the body and the wake it leaves behind.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AeroConfig {
+    pub downforce: f32,            // downforce per squared speed (N s^2/m^2)
+    pub balance: f32,              // share of the downforce on the front axle
+    pub wake_length: f32,          // distance behind the car the wake reaches (m)
+    pub wake_width: f32,           // distance beside the centre line the wake reaches (m)
+    pub slipstream_drag: f32,      // drag lost right behind another car (ratio)
+    pub slipstream_downforce: f32, // downforce lost right behind another car (ratio)
+}
+
+impl Default for AeroConfig {
+    fn default() -> Self {
+        AeroConfig {
+            downforce: 1.5,
+            balance: 0.45,
+            wake_length: 40.0,
+            wake_width: 2.0,
+            slipstream_drag: 0.4,
+            slipstream_downforce: 0.3,
+        }
+    }
+}
+
+/// Temperature and wear of the tires of an axle.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TireState {
+    pub temperature: f32, // °C
+    pub wear: f32,        // share of the tread worn away, in [0,1]
+}
+
+impl Default for TireState {
+    fn default() -> Self {
+        TireState::new(&TireConfig::default())
+    }
+}
+
+impl TireState {
+    pub fn new(config: &TireConfig) -> TireState {
+        TireState {
+            temperature: config.ambient,
+            wear: 0.0,
+        }
+    }
+
+    /// Grip of the tires relative to fresh ones in the optimal window.
+    pub fn grip(&self, config: &TireConfig) -> f32 {
+        let off_window = ((self.temperature - config.optimal).abs() - config.window).max(0.0);
+        let thermal = (1.0 - config.falloff * off_window).max(config.min_grip);
+        let tread = 1.0 - self.wear * (1.0 - config.worn_grip);
+        thermal * tread
+    }
+
+    fn update(&mut self, config: &TireConfig, slip_power: f32, speed: f32, dt: f32) {
+        let cooling = (0.01 + config.cooling * speed) * (self.temperature - config.ambient);
+        self.temperature += (config.heating * slip_power / 1000.0 - cooling) * dt;
+        self.wear = (self.wear + config.wear_rate * slip_power * dt / 1e6).min(1.0);
+    }
+}
+
+/// Which aids intervened in the last update.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ActiveAssists {
+    pub abs: bool,
+    pub traction_control: bool,
+    pub stability_control: bool,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct CarConfig {
     pub gravity: f32,                // m/s^2
     pub mass: f32,                   // kg
@@ -555,12 +929,34 @@ pub struct CarConfig {
     pub air_resistance: f32,         // unitless
     pub roll_resistance: f32,        // unitless
     pub body_height: f32,            // m
+    #[serde(default = "default_brake_lock")]
+    pub brake_lock: f32, // braking force the tires hold before locking (ratio of weight)
+    #[serde(default)]
+    pub assists: Assists,
+    #[serde(default)]
+    pub boost: BoostConfig,
+    #[serde(default)]
+    pub tires: TireConfig,
+    #[serde(default)]
+    pub fuel: FuelConfig,
+    #[serde(default)]
+    pub aero: AeroConfig,
+    #[serde(default)]
+    pub roll: RollConfig,
+}
+
+fn default_brake_lock() -> f32 {
+    0.6
 }
 
 impl CarConfig {
     pub fn wheel_base(&self) -> f32 {
         self.cg_to_rear_axle + self.cg_to_front_axle
     }
+    /// Distance between the centres of the left and right wheels.
+    pub fn track_width(&self) -> f32 {
+        self.width - 2.0 * self.wheel_width
+    }
     pub fn body_size(&self) -> Vec3 {
         Vec3::new(
             self.cg_to_front + self.cg_to_rear,
@@ -576,30 +972,84 @@ pub struct CarComponent {
     pub heading: f32,          // direction of car (rad)
     pub position: Vec2,        // world coordinates
     pub terrain_height: f32,   // height of the terrain below the car
+    pub terrain_slope: Vec2,   // gradient of the terrain below the car
     pub velocity: Vec2,        // world coordinates
     pub acceleration: Vec2,    // world coordinates
     pub angular_velocity: f32, // rad/s
     pub wheel_roll: f32,       // rad
 
+    pub height: f32,            // of the bottom of the wheels (m)
+    pub vertical_velocity: f32, // m/s
+    pub airborne: bool,         // no wheel touches the ground
+    pub pitch: f32,             // nose up (rad)
+    pub roll: f32,              // left side up (rad)
+    pub pitch_rate: f32,        // rad/s
+    pub roll_rate: f32,         // rad/s
+
     pub steer: f32,    // in [-1,1]
     pub throttle: f32, // in [0,1]
     pub brake: f32,    // in [0,1]
+    pub nitro: bool,   // the driver asks for boost
+
+    pub boost: f32,            // nitro left in the tank
+    pub boosting: bool,        // boost is applied
+    pub tires: [TireState; 2], // front and rear axle
+    pub wheel_speed: [f32; 2], // of the front and rear wheels (rad/s)
+    pub fuel: f32,             // kg
+    pub damage: Damage,
+    pub color: Vec4,
+    pub draft: f32, // strength of the wake the car drives in, in [0,1]
+    pub ghost: f32, // time left passing through other cars after a respawn (s)
+    pub active_assists: ActiveAssists,
+
+    pub suspension: [Suspension; 4], // front left, front right, rear right, rear left
+}
 
-    pub rear_susp: Suspension,
-    pub front_susp: Suspension,
-    pub wheels: [Entity; 4],
+/// Gives back its body, wheels and shadow to a car put back on the track.
+#[derive(Debug, Copy, Clone, Message)]
+pub struct RestoreCar(pub Entity);
+
+/// Entities drawing the wheels and the shadow of a car.
+#[derive(Debug, Copy, Clone, Component)]
+pub struct CarParts {
+    pub wheels: [Entity; 4], // in the order of the suspension
     pub shadow: Entity,
 }
 
 impl CarComponent {
-    fn new_stiff(
-        wheels: [Entity; 4],
-        shadow: Entity,
+    /// A car of the base parameters at the origin with the given tires and aids.
+    #[cfg(test)]
+    pub(crate) fn test_car(model: TireModel, assists: Assists) -> CarComponent {
+        let tires = TireConfig {
+            model,
+            ..TireConfig::default()
+        };
+        let spec = CarSpec {
+            color: Vec4::WHITE,
+            assists,
+            tires,
+            randomness: 0.0,
+        };
+        CarComponent::new_stiff(
+            (0.0, Vec2::zero()),
+            spec,
+            &mut XorShiftRng::seed_from_u64(1),
+        )
+    }
+
+    pub(crate) fn new_stiff<R: Rng>(
         init_state: (f32, Vec2),
-        randomness: f32,
+        spec: CarSpec,
+        seed_rng: &mut R,
     ) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut rngs = rand::thread_rng();
+        let CarSpec {
+            color,
+            assists,
+            tires,
+            randomness,
+        } = spec;
+        let mut rng = XorShiftRng::seed_from_u64(seed_rng.gen());
+        let mut rngs = XorShiftRng::seed_from_u64(seed_rng.gen());
         let normal = Normal::new(1.0, randomness as f64).unwrap();
         let normals = Normal::new(1.0, randomness as f64 * 0.1).unwrap();
         let mut rnd = || normal.sample(&mut rng) as f32;
@@ -630,21 +1080,49 @@ impl CarComponent {
                 air_resistance: 2.5 * rnd(),
                 roll_resistance: 10.0 * rnd(),
                 body_height: 1.0 * scale,
+                brake_lock: 0.6 * rnd(),
+                assists,
+                boost: BoostConfig::default(),
+                tires,
+                fuel: FuelConfig::default(),
+                aero: AeroConfig::default(),
+                roll: RollConfig::default(),
             },
             heading: init_state.0,
             position: init_state.1,
             terrain_height: 0.0,
+            terrain_slope: Vec2::zero(),
             velocity: Vec2::zero(),
             acceleration: Vec2::zero(),
             angular_velocity: 0.0,
             steer: 0.0,
             throttle: 0.0,
             brake: 0.0,
-            front_susp: Suspension::new(0.0, 2500.0, 42000.0),
-            rear_susp: Suspension::new(0.05, 2200.0, 34000.0),
-            wheels,
+            nitro: false,
+            boost: BoostConfig::default().capacity,
+            boosting: false,
+            tires: [TireState::new(&tires); 2],
+            wheel_speed: [0.0; 2],
+            fuel: FuelConfig::default().capacity,
+            damage: Damage::default(),
+            color,
+            draft: 0.0,
+            ghost: 0.0,
+            active_assists: ActiveAssists::default(),
+            suspension: [
+                Suspension::new(0.0, 1250.0, 21000.0),
+                Suspension::new(0.0, 1250.0, 21000.0),
+                Suspension::new(0.05, 1100.0, 17000.0),
+                Suspension::new(0.05, 1100.0, 17000.0),
+            ],
             wheel_roll: 0.0,
-            shadow,
+            height: 0.0,
+            vertical_velocity: 0.0,
+            airborne: false,
+            pitch: 0.0,
+            roll: 0.0,
+            pitch_rate: 0.0,
+            roll_rate: 0.0,
         }
     }
 
@@ -676,10 +1154,812 @@ impl CarComponent {
     }
 
     pub fn pos3(&self) -> Vec3 {
-        Vec3::new(self.position.x, self.terrain_height, self.position.y)
+        Vec3::new(self.position.x, self.height, self.position.y)
+    }
+
+    /// Fills up the tank, fits fresh tires and repairs the body, as in a pit stop.
+    pub fn service(&mut self) {
+        self.fuel = self.config.fuel.capacity;
+        self.tires = [TireState::new(&self.config.tires); 2];
+        self.damage = Damage::default();
+    }
+
+    /// Puts the car down at rest on the track, level and with its wheels stopped.
+    pub fn set_down(&mut self, heading: f32, position: Vec2) {
+        self.heading = heading;
+        self.position = position;
+        self.velocity = Vec2::zero();
+        self.acceleration = Vec2::zero();
+        self.angular_velocity = 0.0;
+        self.wheel_speed = [0.0; 2];
+        self.height = 0.0;
+        self.vertical_velocity = 0.0;
+        self.airborne = false;
+        self.pitch = 0.0;
+        self.roll = 0.0;
+        self.pitch_rate = 0.0;
+        self.roll_rate = 0.0;
+    }
+
+    /// Advances the physics of the car by `dt` seconds, driven by its controls.
+    pub fn step(&mut self, dt: f32) {
+        // println!(
+        //     "throttle: {}, brake: {}, steer: {}",
+        //     self.throttle, self.brake, self.steer
+        // );
+
+        // init config
+        let cfg = &self.config;
+        let mass = self.total_mass();
+        let inertia = mass * cfg.inertia_ratio;
+        let wheel_base = cfg.wheel_base();
+        let axle_weight_ratio_front = cfg.cg_to_rear_axle / wheel_base;
+        let axle_weight_ratio_rear = cfg.cg_to_front_axle / wheel_base;
+        let steer_angle = cfg.max_steer * self.steer;
+        let steer_angle = steer_angle / (1.0 + self.speed() / 60.0)
+            + (self.damage.right - self.damage.left) * DAMAGE_MISALIGNMENT;
+
+        // local base
+        let dir = self.dir();
+        let right = self.right();
+
+        // velocity and acceleration in local coordinates
+        let vel_long = self.velocity.dot(dir);
+        let vel_lat = self.velocity.dot(right);
+        let acc_long = self.acceleration.dot(dir);
+        let acc_lat = self.acceleration.dot(right);
+
+        // the wake of a car ahead takes away drag and downforce
+        let aero = cfg.aero;
+        let drag = cfg.air_resistance * (1.0 - aero.slipstream_drag * self.draft);
+        let downforce =
+            aero.downforce * vel_long * vel_long * (1.0 - aero.slipstream_downforce * self.draft);
+
+        // weight on axles, none while the car is in the air so that the tires have no grip
+        let grounded = if self.airborne { 0.0 } else { 1.0 };
+        let axle_weight_front = (mass
+            * (axle_weight_ratio_front * cfg.gravity
+                - cfg.weight_transfer * acc_long * cfg.cg_height / wheel_base)
+            + aero.balance * downforce)
+            * grounded;
+        let axle_weight_rear = (mass
+            * (axle_weight_ratio_rear * cfg.gravity
+                + cfg.weight_transfer * acc_long * cfg.cg_height / wheel_base)
+            + (1.0 - aero.balance) * downforce)
+            * grounded;
+
+        // weight moves to the outer wheels in corners, shared by the axles
+        // in proportion to how stiffly they resist roll
+        let roll = cfg.roll;
+        let roll_front = self.suspension[0].stiffness + roll.anti_roll_front;
+        let roll_rear = self.suspension[3].stiffness + roll.anti_roll_rear;
+        let lateral_transfer =
+            roll.load_transfer * mass * acc_lat * cfg.cg_height / cfg.track_width();
+        let transfer_front = lateral_transfer * roll_front / (roll_front + roll_rear);
+        let transfer_rear = lateral_transfer - transfer_front;
+
+        // weight on wheels, in the order of the wheels
+        let wheel_weight = [
+            (axle_weight_front / 2.0 + transfer_front).max(0.0),
+            (axle_weight_front / 2.0 - transfer_front).max(0.0),
+            (axle_weight_rear / 2.0 - transfer_rear).max(0.0),
+            (axle_weight_rear / 2.0 + transfer_rear).max(0.0),
+        ];
+
+        // velocity of wheels from angular velocity
+        let vel_from_angular_front = cfg.cg_to_front_axle * self.angular_velocity;
+        let vel_from_angular_rear = -cfg.cg_to_rear_axle * self.angular_velocity;
+
+        // sideways and rolling velocity of the tires, the front ones turned with the steering
+        let (steer_sin, steer_cos) = steer_angle.sin_cos();
+        let vel_lat_front = vel_lat + vel_from_angular_front;
+        let slide_front = steer_cos * vel_lat_front - steer_sin * vel_long;
+        let roll_front = steer_cos * vel_long + steer_sin * vel_lat_front;
+        let slide_rear = vel_lat + vel_from_angular_rear;
+
+        // slip angles
+        let slip_angle_front = tire_model::slip_angle(slide_front, roll_front);
+        let slip_angle_rear = tire_model::slip_angle(slide_rear, vel_long);
+
+        let assists = cfg.assists;
+        let mut active = ActiveAssists::default();
+        let moving = self.speed() > 1.0;
+
+        // slip of the spinning wheels against the road, in the order front, rear
+        let model = cfg.tires.model;
+        let wheel_slip = [
+            tire_model::slip_ratio(self.wheel_speed[0] * cfg.wheel_radius, roll_front),
+            tire_model::slip_ratio(self.wheel_speed[1] * cfg.wheel_radius, vel_long),
+        ];
+        let peak_slip = model.peak_slip_ratio();
+
+        let mut f_brake = self.brake * cfg.brake_force; // todo hand break
+        let mut brake_share = [BRAKE_BIAS, 1.0 - BRAKE_BIAS];
+        let mut locked = false;
+        if model.spins_wheels() {
+            // ABS lets go of the brakes of wheels sliding past the peak of their tires
+            for k in 0..2 {
+                if assists.abs
+                    && moving
+                    && f_brake > 0.0
+                    && wheel_slip[k] < -assists.abs_slip * peak_slip
+                {
+                    brake_share[k] = 0.0;
+                    active.abs = true;
+                }
+            }
+        } else if assists.grip_limits {
+            // brakes lock the wheels when they ask for more than the tires hold
+            let f_brake_lock = cfg.brake_lock * (mass * cfg.gravity + downforce);
+            if assists.abs && moving && f_brake > assists.abs_slip * f_brake_lock {
+                f_brake = assists.abs_slip * f_brake_lock;
+                active.abs = true;
+            }
+            locked = moving && f_brake > f_brake_lock;
+            if locked {
+                f_brake = cfg.lock_grip * f_brake_lock;
+            }
+        }
+
+        // the engine burns fuel with the throttle, without fuel it has no power
+        self.fuel = (self.fuel - cfg.fuel.burn_rate * self.throttle.min(1.0) * dt).max(0.0);
+        let engine_on = self.fuel > 0.0;
+
+        // nitro burns while boosting and refills over time and while drifting
+        self.boosting = self.nitro && engine_on && self.throttle > 0.0 && self.boost > 0.0;
+        if self.boosting {
+            self.boost = (self.boost - cfg.boost.burn_rate * dt).max(0.0);
+        } else {
+            let drift = (slip_angle_rear.abs() - 0.1).max(0.0) * slide_weight(self.speed());
+            self.boost = (self.boost
+                + (cfg.boost.recharge_rate + cfg.boost.drift_recharge * drift) * dt)
+                .min(cfg.boost.capacity);
+        }
+        let f_boost = if self.boosting { cfg.boost.force } else { 0.0 };
+
+        let mut f_throttle = if engine_on {
+            self.throttle * cfg.engine_force * (1.0 - DAMAGE_POWER_LOSS * self.damage.front)
+                + f_boost
+        } else {
+            0.0
+        };
+        if assists.traction_control
+            && f_throttle > 0.0
+            && slip_angle_rear.abs() > assists.tc_slip_angle
+        {
+            f_throttle *= (assists.tc_slip_angle / slip_angle_rear.abs()).powi(2);
+            active.traction_control = true;
+        }
+        if assists.traction_control
+            && f_throttle > 0.0
+            && model.spins_wheels()
+            && wheel_slip[1] > peak_slip
+        {
+            f_throttle *= (peak_slip / wheel_slip[1]).powi(2);
+            active.traction_control = true;
+        }
+
+        // yaw rate beyond what the front wheels steer is fought by braking single wheels
+        let steered_yaw = vel_long * steer_angle.tan() / wheel_base;
+        let excess_yaw = self.angular_velocity - steered_yaw;
+        let mut esc_torque = 0.0;
+        let esc_weight = slide_weight(self.speed());
+        if assists.stability_control && esc_weight > 0.0 && excess_yaw.abs() > assists.esc_yaw_rate
+        {
+            // fades in with the speed and the excess so that it never kicks in abruptly
+            let excess = excess_yaw.abs() - assists.esc_yaw_rate;
+            esc_torque = -excess
+                * excess_yaw.signum()
+                * assists.esc_strength
+                * inertia
+                * grounded
+                * esc_weight;
+            f_throttle *=
+                1.0 - 0.5 * esc_weight * (excess / assists.esc_yaw_rate.max(1e-3)).min(1.0);
+            active.stability_control = true;
+        }
+
+        let lock_ratio = if locked { cfg.lock_grip } else { 1.0 };
+        let tire_grip_front = cfg.tire_grip * lock_ratio * self.tires[0].grip(&cfg.tires);
+        // traction of the rear wheels uses up part of their lateral grip,
+        // spinning wheels share it in the friction ellipse instead
+        let traction_ratio = if model.spins_wheels() || !assists.grip_limits {
+            0.0
+        } else {
+            f_throttle / (cfg.tire_grip * axle_weight_rear.max(1.0))
+        };
+        let tire_grip_rear = cfg.tire_grip
+            * lock_ratio
+            * self.tires[1].grip(&cfg.tires)
+            * (1.0 - traction_ratio.min(1.0).powi(2)).sqrt(); // todo add support for hand break
+
+        // the more load a tire carries the less grip it has for each newton of it
+        let static_weight_front = mass * cfg.gravity * axle_weight_ratio_front / 2.0;
+        let static_weight_rear = mass * cfg.gravity * axle_weight_ratio_rear / 2.0;
+        let axle_friction = |slip_angle: f32,
+                             corner_stiffness: f32,
+                             grip: f32,
+                             wheels: [usize; 2],
+                             static_weight: f32| {
+            wheels
+                .iter()
+                .map(|k| {
+                    let weight = wheel_weight[*k];
+                    let sensitivity = cfg.tires.load_sensitivity;
+                    let grip = grip * (1.0 - sensitivity * (weight / static_weight - 1.0));
+                    model.lateral(slip_angle, corner_stiffness, grip.max(0.0)) * weight
+                })
+                .sum::<f32>()
+        };
+        let mut f_friction_front = axle_friction(
+            slip_angle_front,
+            cfg.corner_stiffness_front,
+            tire_grip_front,
+            [0, 1],
+            static_weight_front,
+        );
+        let mut f_friction_rear = axle_friction(
+            slip_angle_rear,
+            cfg.corner_stiffness_rear,
+            tire_grip_rear,
+            [2, 3],
+            static_weight_rear,
+        );
+
+        // the engine spins up the rear wheels, the brakes slow down both axles and
+        // the tires pull them towards the speed of the road
+        let speed = self.speed();
+        let mut f_wheels = [0.0; 2];
+        let mut wheel_slip_power = [0.0; 2];
+        if model.spins_wheels() {
+            let drive_rear = if self.wheel_speed[1] * cfg.wheel_radius < REDLINE_SPEED {
+                f_throttle
+            } else {
+                0.0
+            };
+            let axles = [
+                (axle_weight_front, tire_grip_front, 0.0, roll_front),
+                (axle_weight_rear, tire_grip_rear, drive_rear, vel_long),
+            ];
+            for (k, (weight, grip, drive, road_speed)) in axles.iter().copied().enumerate() {
+                let wheel = tire_model::Wheel {
+                    radius: cfg.wheel_radius,
+                    inertia: WHEEL_INERTIA,
+                    weight,
+                    grip,
+                    drive,
+                    brake: f_brake * brake_share[k],
+                };
+                let omega = self.wheel_speed[k];
+                self.wheel_speed[k] = tire_model::spin_wheel(&model, &wheel, omega, road_speed, dt);
+
+                let tread_speed = self.wheel_speed[k] * cfg.wheel_radius;
+                let slip = tire_model::slip_ratio(tread_speed, road_speed);
+                f_wheels[k] = model.longitudinal(slip, grip) * weight;
+                wheel_slip_power[k] = f_wheels[k].abs() * (tread_speed - road_speed).abs();
+            }
+
+            let (peak_lat, peak_long) = model.peaks(tire_grip_front);
+            let (long, lat) = tire_model::friction_ellipse(
+                f_wheels[0],
+                f_friction_front,
+                peak_long * axle_weight_front,
+                peak_lat * axle_weight_front,
+            );
+            f_wheels[0] = long;
+            f_friction_front = lat;
+            let (peak_lat, peak_long) = model.peaks(tire_grip_rear);
+            let (long, lat) = tire_model::friction_ellipse(
+                f_wheels[1],
+                f_friction_rear,
+                peak_long * axle_weight_rear,
+                peak_lat * axle_weight_rear,
+            );
+            f_wheels[1] = long;
+            f_friction_rear = lat;
+        } else {
+            self.wheel_speed = [vel_long / cfg.wheel_radius; 2];
+            if locked {
+                wheel_slip_power = [f_brake * speed / 2.0; 2];
+            }
+        }
+
+        // the tires of an axle can at most stop half the sliding of their contact point within
+        // the step, which keeps them from overshooting at low speed where the slip angles
+        // change quickly
+        let settle = |force: f32, slide: f32, arm: f32| {
+            let contact_mass = 0.5 / (1.0 / mass + arm * arm / inertia);
+            let limit = contact_mass * slide.abs() / dt.max(1e-6);
+            force.clamp(-limit, limit)
+        };
+        let f_friction_front = settle(f_friction_front, slide_front, cfg.cg_to_front_axle);
+        let f_friction_rear = settle(f_friction_rear, slide_rear, cfg.cg_to_rear_axle);
+
+        // sliding tires heat up and wear with the power of the slip
+        let slip_power = |force: f32, slip_angle: f32, wheel_slip_power: f32| {
+            force.abs() * speed * slip_angle.sin().abs() + wheel_slip_power
+        };
+        let slip_power_front = slip_power(f_friction_front, slip_angle_front, wheel_slip_power[0]);
+        let slip_power_rear = slip_power(f_friction_rear, slip_angle_rear, wheel_slip_power[1]);
+        self.tires[0].update(&cfg.tires, slip_power_front, speed, dt);
+        self.tires[1].update(&cfg.tires, slip_power_rear, speed, dt);
+
+        // brake and throttle local force
+        let (f_traction, f_traction_lat) = if model.spins_wheels() {
+            (
+                f_wheels[0] * steer_angle.cos() + f_wheels[1],
+                f_wheels[0] * steer_angle.sin(),
+            )
+        } else {
+            // brakes stop the car but never push it backwards
+            let f_brake = f_brake.min(mass * vel_long.abs() / dt.max(1e-6));
+            ((f_throttle - f_brake * vel_long.signum()) * grounded, 0.0)
+        };
+
+        let roll_resistance = cfg.roll_resistance * grounded;
+        let f_drag_long = -roll_resistance * vel_long - drag * vel_long * vel_long.abs();
+        let f_drag_lat = -roll_resistance * vel_lat - drag * vel_lat * vel_lat.abs();
+
+        // total local force
+        let f_tot_long = f_drag_long - steer_angle.sin() * f_friction_front + f_traction;
+        let f_tot_lat =
+            f_drag_lat + steer_angle.cos() * f_friction_front + f_friction_rear + f_traction_lat;
+
+        // local acceleration
+        let a_long = f_tot_long / mass;
+        let a_lat = f_tot_lat / mass;
+
+        // acceleration in world coordinates
+        let a = a_long * dir + a_lat * right;
+
+        // anti-roll bars push down the side that rides higher
+        let lengths = self.suspension.map(|s| s.length);
+        for k in 0..4 {
+            let (bar, damage) = if k < 2 {
+                (roll.anti_roll_front, self.damage.front)
+            } else {
+                (roll.anti_roll_rear, self.damage.rear)
+            };
+            let f_bar = bar * (lengths[k] - lengths[k ^ 1]);
+            self.suspension[k].damage = damage;
+            self.suspension[k].apply_force(wheel_weight[k] + f_bar, dt, mass / 2.0);
+        }
+
+        self.acceleration = a;
+        self.velocity += self.acceleration * dt;
+
+        // gravity pulls the car down slopes
+        let slope = self.terrain_slope;
+        if !self.airborne {
+            self.velocity -= slope * (cfg.gravity / (1.0 + slope.dot(slope)) * dt);
+        }
+
+        // off the throttle resistance builds up towards standstill and holds the car there,
+        // also on slopes
+        let speed = self.speed();
+        if !self.airborne && speed > 0.0 {
+            let hold = STANDSTILL_DECELERATION
+                * (1.0 - speed / STANDSTILL_SPEED).max(0.0)
+                * (1.0 - self.throttle.min(1.0))
+                * dt;
+            self.velocity *= (speed - hold).max(0.0) / speed;
+        }
+
+        // rotational forces
+        let body_torque = (f_friction_front + f_traction_lat) * cfg.cg_to_front_axle
+            - f_friction_rear * cfg.cg_to_rear_axle
+            + esc_torque;
+
+        let angular_acceleration = body_torque / inertia;
+
+        self.angular_velocity += angular_acceleration * dt;
+        self.heading += self.angular_velocity * dt;
+
+        self.position += self.velocity * dt;
+
+        self.wheel_roll += self.wheel_speed[1] * dt;
+
+        self.active_assists = active;
+        self.ghost = (self.ghost - dt).max(0.0);
+
+        // the car follows the ground until it falls away faster than gravity pulls the car down
+        let ground = self.terrain_height;
+        let ground_rate = self.velocity.dot(slope);
+        let ground_pitch = dir.dot(slope).atan();
+        let ground_roll = -right.dot(slope).atan();
+        self.vertical_velocity -= cfg.gravity * dt;
+        self.height += self.vertical_velocity * dt;
+        if self.height <= ground {
+            if self.airborne {
+                self.land(ground_rate, ground_pitch, ground_roll);
+            }
+            self.height = ground;
+            self.vertical_velocity = ground_rate;
+        }
+        // the wheels keep touching the ground while their springs stretch
+        self.airborne = self.height > ground + WHEEL_DROOP;
+
+        if self.airborne {
+            // the body keeps turning with the angular momentum it took off with
+            self.pitch += self.pitch_rate * dt;
+            self.roll += self.roll_rate * dt;
+        } else {
+            let follow = (dt / ATTITUDE_LAG).min(1.0);
+            let pitch_rate = (ground_pitch - self.pitch) / dt.max(1e-6);
+            let roll_rate = (ground_roll - self.roll) / dt.max(1e-6);
+            self.pitch_rate += (pitch_rate - self.pitch_rate) * follow;
+            self.roll_rate += (roll_rate - self.roll_rate) * follow;
+            self.pitch = ground_pitch;
+            self.roll = ground_roll;
+        }
+    }
+
+    /// Puts the car down on the ground rising at `ground_rate`. The springs take up the speed
+    /// each corner hits the ground with and the body is turned to the attitude of the ground,
+    /// compressing the springs of the corners that come down first.
+    fn land(&mut self, ground_rate: f32, ground_pitch: f32, ground_roll: f32) {
+        let cfg = &self.config;
+        let half_track = cfg.track_width() / 2.0;
+        let corners = [
+            (cfg.cg_to_front_axle, half_track),
+            (cfg.cg_to_front_axle, -half_track),
+            (-cfg.cg_to_rear_axle, -half_track),
+            (-cfg.cg_to_rear_axle, half_track),
+        ];
+        for (k, (x, y)) in corners.iter().copied().enumerate() {
+            let speed = self.vertical_velocity + self.pitch_rate * x + self.roll_rate * y;
+            let offset =
+                (self.pitch - ground_pitch).sin() * x + (self.roll - ground_roll).sin() * y;
+            let spring = &mut self.suspension[k];
+            spring.velocity += speed - ground_rate;
+            spring.length =
+                (spring.length + offset.min(0.0)).max(spring.eq_length - SUSPENSION_TRAVEL);
+        }
+        self.pitch_rate = 0.0;
+        self.roll_rate = 0.0;
+    }
+
+    /// Strength of the wake of the leader at this car, fading with distance and offset and
+    /// building up with the square of the speed of the leader until it is fully formed.
+    fn draft_behind(&self, leader: &CarComponent) -> f32 {
+        let aero = &leader.config.aero;
+        let formed = (leader.speed() / WAKE_SPEED).min(1.0).powi(2);
+        let d = self.position - leader.position;
+        let behind = -d.dot(leader.dir()) - leader.config.cg_to_rear;
+        let beside = d.dot(leader.right()).abs();
+        if behind < 0.0 || behind > aero.wake_length || beside > aero.wake_width {
+            return 0.0;
+        }
+        formed * (1.0 - behind / aero.wake_length) * (1.0 - beside / aero.wake_width)
+    }
+
+    /// Centres of the two circles covering the body.
+    fn hull(&self) -> [Vec2; 2] {
+        let cfg = &self.config;
+        let mid = self.position + self.dir() * (cfg.cg_to_front - cfg.cg_to_rear) / 2.0;
+        let half = ((cfg.cg_to_front + cfg.cg_to_rear - cfg.width) / 2.0).max(0.0);
+        [mid + self.dir() * half, mid - self.dir() * half]
+    }
+
+    /// Point of contact, normal towards the other car and depth of the overlap of the bodies.
+    fn contact(&self, other: &CarComponent) -> Option<(Vec2, Vec2, f32)> {
+        let reach = (self.config.width + other.config.width) / 2.0;
+        let mut best: Option<(Vec2, Vec2, f32)> = None;
+        for p in self.hull().iter() {
+            for q in other.hull().iter() {
+                let d = *q - *p;
+                let dist = d.length();
+                if dist < reach && dist > 0.0001 && best.is_none_or(|b| reach - dist > b.2) {
+                    let n = d / dist;
+                    best = Some((*p + n * self.config.width / 2.0, n, reach - dist));
+                }
+            }
+        }
+        best
+    }
+
+    /// Pushes the two cars apart if they overlap, exchanging momentum and, with `damage`, turning
+    /// the energy of the impact into damage of the zones hit. Returns whether they touched.
+    fn impact(&mut self, other: &mut CarComponent, damage: bool) -> bool {
+        let (a, b) = (*self, *other);
+        if a.ghost > 0.0 || b.ghost > 0.0 {
+            return false;
+        }
+        let (point, n, depth) = match a.contact(&b) {
+            Some(contact) => contact,
+            None => return false,
+        };
+        self.position -= n * depth / 2.0;
+        other.position += n * depth / 2.0;
+
+        let (ma, mb) = (a.total_mass(), b.total_mass());
+        let (ia, ib) = (ma * a.config.inertia_ratio, mb * b.config.inertia_ratio);
+        let (ra, rb) = (point - a.position, point - b.position);
+        let va = a.velocity + ra.perp() * a.angular_velocity;
+        let vb = b.velocity + rb.perp() * b.angular_velocity;
+        let closing = (vb - va).dot(n);
+        if closing >= 0.0 {
+            return true;
+        }
+
+        let (ka, kb) = (ra.perp().dot(n), rb.perp().dot(n));
+        let inv_mass = 1.0 / ma + 1.0 / mb;
+        let impulse = -(1.0 + RESTITUTION) * closing / (inv_mass + ka * ka / ia + kb * kb / ib);
+        self.velocity -= n * (impulse / ma);
+        self.angular_velocity -= impulse * ka / ia;
+        other.velocity += n * (impulse / mb);
+        other.angular_velocity += impulse * kb / ib;
+
+        if damage {
+            let energy = 0.5 * closing * closing * (1.0 - RESTITUTION * RESTITUTION) / inv_mass;
+            self.damage.hit(a.zone_at(point), energy / ZONE_STRENGTH);
+            other.damage.hit(b.zone_at(point), energy / ZONE_STRENGTH);
+        }
+        true
+    }
+
+    /// Zone of the body nearest to the given point.
+    fn zone_at(&self, p: Vec2) -> Zone {
+        let local = p - self.position;
+        let x = local.dot(self.dir()) / (self.config.body_size().x / 2.0);
+        let y = local.dot(self.right()) / (self.config.width / 2.0);
+        match (x.abs() >= y.abs(), x > 0.0, y > 0.0) {
+            (true, true, _) => Zone::Front,
+            (true, false, _) => Zone::Rear,
+            (false, _, true) => Zone::Right,
+            (false, _, false) => Zone::Left,
+        }
+    }
+
+    /// Mass of the car with the fuel it carries (kg).
+    pub fn total_mass(&self) -> f32 {
+        self.config.mass + self.fuel
+    }
+
+    /// Share of the nitro tank that is full.
+    pub fn boost_level(&self) -> f32 {
+        self.boost / self.config.boost.capacity.max(0.001)
     }
 
     pub fn wheel_turn(&self) -> f32 {
         self.config.max_steer * self.steer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn car(model: TireModel) -> CarComponent {
+        CarComponent::test_car(model, Assists::default())
+    }
+
+    // a car of each tire model with the aids of each preset, named for the failure messages
+    fn cars() -> Vec<(String, CarComponent)> {
+        let mut cars = vec![];
+        for model in [TireModel::Linear, TireModel::magic_formula()].iter() {
+            for preset in CarPresets::default().cars.iter() {
+                let name = if model.spins_wheels() {
+                    "magic"
+                } else {
+                    "linear"
+                };
+                let name = format!("{} {}", name, preset.name);
+                cars.push((name, CarComponent::test_car(*model, preset.assists)));
+            }
+        }
+        cars
+    }
+
+    #[test]
+    fn state_is_continuous_through_the_old_low_speed_threshold() {
+        // the presets include the aids of the AI, whose ESC blends in over this range
+        for (name, mut car) in cars() {
+            car.throttle = 1.0;
+            car.steer = 0.2;
+            let mut crossed = false;
+            for _ in 0..1200 {
+                let before = car;
+                car.step(DT);
+                if before.speed() < SLIDE_FADE_END && car.speed() >= SLIDE_FADE_END {
+                    crossed = true;
+                }
+                assert!((car.speed() - before.speed()).abs() < 0.5, "{}", name);
+                assert!(
+                    (car.angular_velocity - before.angular_velocity).abs() < 0.1,
+                    "{}",
+                    name
+                );
+                assert!((car.velocity - before.velocity).length() < 0.5, "{}", name);
+            }
+            assert!(crossed, "{} never got up to speed", name);
+        }
+    }
+
+    #[test]
+    fn cars_can_spin_at_low_speed() {
+        for (name, mut car) in cars() {
+            car.config.tire_grip = 0.3;
+            car.velocity = Vec2::new(8.0, 0.0);
+            car.angular_velocity = 3.0;
+            car.step(DT);
+            assert!(car.angular_velocity > 2.5, "{}", name);
+        }
+    }
+
+    #[test]
+    fn tires_have_no_grip_in_the_air() {
+        for (name, mut car) in cars() {
+            car.velocity = Vec2::new(30.0, 0.0);
+            car.steer = 1.0;
+            car.height = 2.0;
+            car.airborne = true;
+            car.step(DT);
+            assert!(car.airborne, "{}", name);
+            assert_eq!(car.angular_velocity, 0.0, "{}", name);
+            assert_eq!(car.velocity.dot(car.right()), 0.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn landings_are_absorbed_by_the_springs() {
+        let mut car = car(TireModel::magic_formula());
+        for _ in 0..120 {
+            car.step(DT);
+        }
+        let settled = car.suspension[0].length;
+        car.height = 1.0;
+        car.airborne = true;
+        let mut deepest = settled;
+        for _ in 0..120 {
+            car.step(DT);
+            deepest = deepest.min(car.suspension[0].length);
+        }
+        assert!(!car.airborne);
+        assert_eq!(car.height, 0.0);
+        assert!(deepest < settled - 0.05);
+    }
+
+    #[test]
+    fn sideways_slides_fade_instead_of_snapping() {
+        for (name, mut car) in cars() {
+            car.velocity = Vec2::new(0.0, 5.0);
+            car.step(DT);
+            let slide = car.velocity.dot(car.right());
+            assert!(slide > 3.0 && slide < 5.0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parked_car_stays_put() {
+        for (name, mut car) in cars() {
+            car.steer = 1.0;
+            for _ in 0..600 {
+                car.step(DT);
+            }
+            assert!(car.position.length() < 1e-3, "{}", name);
+            assert!(car.angular_velocity.abs() < 1e-3, "{}", name);
+        }
+    }
+
+    #[test]
+    fn braking_car_stops_without_reversing() {
+        for (name, mut car) in cars() {
+            car.velocity = Vec2::new(10.0, 0.0);
+            car.brake = 1.0;
+            for _ in 0..600 {
+                car.step(DT);
+                assert!(car.velocity.dot(car.dir()) >= -1e-3, "{}", name);
+            }
+            assert!(car.speed() < 0.1, "{}", name);
+        }
+    }
+
+    #[test]
+    fn coasting_car_comes_to_rest_smoothly() {
+        for (name, mut car) in cars() {
+            car.velocity = Vec2::new(0.9 * STANDSTILL_SPEED, 0.0);
+            for _ in 0..1200 {
+                let before = car;
+                car.step(DT);
+                assert!(car.speed() <= before.speed() + 1e-4, "{}", name);
+                assert!(before.speed() - car.speed() < 0.2, "{}", name);
+            }
+            assert!(car.speed() < 1e-3, "{}", name);
+        }
+    }
+
+    // two cars nose to nose, closing at twice the given speed
+    fn head_on(speed: f32) -> (CarComponent, CarComponent) {
+        let mut a = car(TireModel::Linear);
+        let mut b = car(TireModel::Linear);
+        a.velocity = Vec2::new(speed, 0.0);
+        b.set_down(PI, Vec2::new(4.0, 0.0));
+        b.velocity = Vec2::new(-speed, 0.0);
+        (a, b)
+    }
+
+    #[test]
+    fn zones_split_the_body_along_its_diagonals() {
+        let car = car(TireModel::Linear);
+        let (half_length, half_width) = (car.config.body_size().x / 2.0, car.config.width / 2.0);
+        let at = |x: f32, y: f32| car.zone_at(Vec2::new(x * half_length, y * half_width));
+
+        assert_eq!(at(0.9, 0.0), Zone::Front);
+        assert_eq!(at(-0.9, 0.0), Zone::Rear);
+        assert_eq!(at(0.0, 0.9), Zone::Right);
+        assert_eq!(at(0.0, -0.9), Zone::Left);
+        assert_eq!(at(0.6, 0.5), Zone::Front);
+        assert_eq!(at(0.5, 0.6), Zone::Right);
+        assert_eq!(at(-0.5, -0.6), Zone::Left);
+        // a corner counts towards the end of the car
+        assert_eq!(at(0.5, 0.5), Zone::Front);
+        assert_eq!(at(-0.5, 0.5), Zone::Rear);
+    }
+
+    #[test]
+    fn hits_build_up_to_a_wrecked_zone() {
+        let mut damage = Damage::default();
+        damage.hit(Zone::Left, 0.3);
+        damage.hit(Zone::Left, 0.3);
+        assert!((damage.left - 0.6).abs() < 1e-6);
+        assert_eq!(damage.worst(), damage.left);
+        damage.hit(Zone::Left, 0.7);
+        assert_eq!(damage.left, 1.0);
+        assert_eq!(damage.right + damage.front + damage.rear, 0.0);
+    }
+
+    #[test]
+    fn a_head_on_impact_bounces_the_cars_and_damages_their_fronts() {
+        let (mut a, mut b) = head_on(5.0);
+        assert!(a.impact(&mut b, true));
+        assert!(a.velocity.x < 0.0 && b.velocity.x > 0.0);
+        assert!(a.damage.front > 0.0 && a.damage.front < 1.0);
+        assert!((a.damage.front - b.damage.front).abs() < 1e-6);
+        assert_eq!(a.damage.rear + a.damage.left + a.damage.right, 0.0);
+
+        // every further impact adds to the damage
+        let first = a.damage.front;
+        let (mut c, mut d) = head_on(5.0);
+        c.damage = a.damage;
+        assert!(c.impact(&mut d, true));
+        assert!((c.damage.front - 2.0 * first).abs() < 1e-4);
+    }
+
+    #[test]
+    fn impacts_spare_ghosts_and_cars_without_damage() {
+        let (mut a, mut b) = head_on(5.0);
+        a.ghost = 1.0;
+        assert!(!a.impact(&mut b, true));
+        assert_eq!(a.velocity.x, 5.0);
+
+        let (mut a, mut b) = head_on(5.0);
+        assert!(a.impact(&mut b, false));
+        assert_eq!(a.damage.worst() + b.damage.worst(), 0.0);
+    }
+
+    #[test]
+    fn the_wake_builds_up_with_the_speed_of_the_leader() {
+        let mut leader = car(TireModel::Linear);
+        let mut follower = car(TireModel::Linear);
+        follower.position = Vec2::new(-10.0, 0.0);
+
+        let mut last = follower.draft_behind(&leader);
+        assert_eq!(last, 0.0);
+        for k in 1..=60 {
+            let speed = k as f32 * 0.5;
+            leader.velocity = Vec2::new(speed, 0.0);
+            let draft = follower.draft_behind(&leader);
+            assert!(
+                draft >= last && draft - last < 0.1,
+                "{} at {} m/s",
+                draft,
+                speed
+            );
+            last = draft;
+        }
+        assert!(last > 0.5);
+    }
+}
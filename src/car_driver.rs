@@ -1,11 +1,18 @@
 use crate::car::CarComponent;
 
+use crate::gamepad::PadInput;
+use crate::input::{Action, Controls};
+use crate::menu;
 use crate::playback_driver::CarDriveState;
+use crate::race_control::{RaceControl, RaceStatus, Respawn};
 use glui::mecs::*;
 use glui::tools::serde_tools::SerdeJsonQuick;
-use glui::tools::*;
 use std::time::Duration;
 
+/// Hands the car of a local player to the AI, as in the demo behind the main menu.
+#[derive(Copy, Clone, Debug, Component)]
+pub struct Autopilot;
+
 #[derive(Copy, Clone, Debug)]
 pub enum Steering {
     Left,
@@ -15,6 +22,8 @@ pub enum Steering {
 
 pub struct CarDriver {
     pub car: Entity,
+    pub player: usize,
+    pub controls: Controls,
     pub braking: bool,
     pub throttling: bool,
     pub nitro: bool,
@@ -26,11 +35,25 @@ pub struct CarDriver {
 
 impl System for CarDriver {
     fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
-        let mut car = world.component_mut::<CarComponent>(self.car).unwrap();
+        if menu::is_paused(world) || world.component::<Autopilot>(self.car).is_some() {
+            return;
+        }
+        let throttle_limit = world
+            .component::<RaceStatus>(self.car)
+            .map(|s| s.throttle_limit())
+            .unwrap_or(1.0);
+        let pad = world.component::<PadInput>(self.car).copied();
+        let mut car = match world.component_mut::<CarComponent>(self.car) {
+            Some(car) => car,
+            None => return,
+        };
 
         let dt = delta_time.as_secs_f32();
 
         match self.steering {
+            Steering::None if pad.is_some() => {
+                car.steer = pad.map(|p| p.steer).unwrap_or_default();
+            }
             Steering::Left => {
                 car.steer = (car.steer - 2.0 * dt).clamp(-1.0, 1.0);
             }
@@ -47,14 +70,20 @@ impl System for CarDriver {
             }
         }
 
-        car.brake = if self.braking { 1.0 } else { 0.0 };
-        car.throttle = if self.throttling { 1.0 } else { 0.0 };
-        car.throttle += if self.nitro { 3.0 } else { 0.0 };
+        let pad = pad.unwrap_or_default();
+        car.brake = if self.braking { 1.0 } else { pad.brake };
+        car.throttle = if self.throttling { 1.0 } else { pad.throttle };
+        car.nitro = self.nitro || pad.nitro;
+        if throttle_limit < 1.0 {
+            car.throttle = car.throttle.min(throttle_limit);
+            car.nitro = false;
+        }
 
         let new_state = CarDriveState {
             throttle: car.throttle,
             steer: car.steer,
             brake: car.brake,
+            nitro: car.nitro,
             update_id: self.update_id,
         };
 
@@ -67,46 +96,39 @@ impl System for CarDriver {
         }
     }
     fn window_event(&mut self, event: &GlutinWindowEvent, world: &mut StaticWorld) -> bool {
-        let car = world.component_mut::<CarComponent>(self.car).unwrap();
-
-        if let GlutinWindowEvent::KeyboardInput { input, .. } = event {
-            let press = input.state == GlutinElementState::Pressed;
-            if let Some(key) = input.virtual_keycode {
-                if key == GlutinKey::W {
-                    self.throttling = press;
-                }
-                if key == GlutinKey::K {
-                    self.nitro = press;
-                }
-                if key == GlutinKey::S {
-                    self.braking = press;
-                }
-                match (self.steering, key, press) {
-                    (Steering::None, GlutinKey::A, true) => self.steering = Steering::Left,
-                    (Steering::None, GlutinKey::D, true) => self.steering = Steering::Right,
-                    (Steering::Right, GlutinKey::A, true) => self.steering = Steering::None,
-                    (Steering::Left, GlutinKey::D, true) => self.steering = Steering::None,
-                    (Steering::Left, GlutinKey::A, false) => self.steering = Steering::None,
-                    (Steering::Right, GlutinKey::D, false) => self.steering = Steering::None,
-                    (Steering::None, GlutinKey::A, false) => self.steering = Steering::Right,
-                    (Steering::None, GlutinKey::D, false) => self.steering = Steering::Left,
-                    _ => {}
-                }
-                if key == GlutinKey::R && !press {
-                    car.position = Vec2::origin();
-                    car.velocity = Vec2::zero();
-                    car.acceleration = Vec2::zero();
-                    car.heading = 0.0;
-                    car.angular_velocity = 0.0;
-                    self.states = vec![];
-                    self.update_id = 0;
-                }
-                // if key == GlutinKey::M && !press {
-                //     println!("State: ({},{:?})", car.heading, car.position);
-                // }
-                if key == GlutinKey::P && !press {
-                    self.states.save_json("path.json").unwrap();
-                }
+        if world.component::<Autopilot>(self.car).is_some() {
+            return false;
+        }
+        if let Some((action, press)) = self.controls.action(event, Some(self.player)) {
+            match action {
+                Action::Throttle => self.throttling = press,
+                Action::Nitro => self.nitro = press,
+                Action::Brake => self.braking = press,
+                _ => {}
+            }
+            let left = action == Action::SteerLeft;
+            let right = action == Action::SteerRight;
+            match (self.steering, left, right, press) {
+                (Steering::None, true, _, true) => self.steering = Steering::Left,
+                (Steering::None, _, true, true) => self.steering = Steering::Right,
+                (Steering::Right, true, _, true) => self.steering = Steering::None,
+                (Steering::Left, _, true, true) => self.steering = Steering::None,
+                (Steering::Left, true, _, false) => self.steering = Steering::None,
+                (Steering::Right, _, true, false) => self.steering = Steering::None,
+                (Steering::None, true, _, false) => self.steering = Steering::Right,
+                (Steering::None, _, true, false) => self.steering = Steering::Left,
+                _ => {}
+            }
+            if action == Action::ResetCar && !press && !menu::is_paused(world) {
+                world.send_by_type::<RaceControl, _>(Respawn(self.car));
+                self.states = vec![];
+                self.update_id = 0;
+            }
+            // if key == GlutinKey::M && !press {
+            //     println!("State: ({},{:?})", car.heading, car.position);
+            // }
+            if action == Action::SavePath && !press {
+                self.states.save_json(&self.path_file("path")).unwrap();
             }
         }
 
@@ -115,9 +137,11 @@ impl System for CarDriver {
 }
 
 impl CarDriver {
-    pub fn new(car: Entity) -> CarDriver {
+    pub fn new(car: Entity, player: usize, controls: Controls) -> CarDriver {
         CarDriver {
             car,
+            player,
+            controls,
             braking: false,
             throttling: false,
             steering: Steering::None,
@@ -127,10 +151,19 @@ impl CarDriver {
             states: vec![],
         }
     }
+
+    // recordings of the second player go to separate files
+    fn path_file(&self, name: &str) -> String {
+        if self.player == 0 {
+            format!("{}.json", name)
+        } else {
+            format!("{}_{}.json", name, self.player + 1)
+        }
+    }
 }
 
 impl Drop for CarDriver {
     fn drop(&mut self) {
-        self.states.save_json("last_path.json").unwrap();
+        self.states.save_json(&self.path_file("last_path")).unwrap();
     }
 }
@@ -0,0 +1,271 @@
+use crate::input::{Action, Controls};
+use crate::menu;
+use crate::race_control;
+use crate::results::SessionResult;
+use crate::track::{Track, TRACK_FILE};
+
+extern crate serde;
+
+use glui::mecs::*;
+use glui::tools::serde_tools::SerdeJsonQuick;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "championship.json";
+const STATE_FILE: &str = "championship_state.json";
+const INTERMISSION: f32 = 15.0; // time the standings are shown between rounds (s)
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChampionshipConfig {
+    pub tracks: Vec<String>,
+    pub laps: usize,
+    pub points: Vec<u32>, // points by finishing position
+}
+
+impl Default for ChampionshipConfig {
+    fn default() -> Self {
+        ChampionshipConfig {
+            tracks: vec![TRACK_FILE.to_owned()],
+            laps: 3,
+            points: vec![25, 18, 15, 12, 10, 8, 6, 4, 2, 1],
+        }
+    }
+}
+
+impl ChampionshipConfig {
+    pub fn load() -> ChampionshipConfig {
+        match ChampionshipConfig::load_json(CONFIG_FILE) {
+            Ok(config) if !config.tracks.is_empty() => config,
+            Ok(_) => {
+                println!("{} lists no tracks, using defaults", CONFIG_FILE);
+                ChampionshipConfig::default()
+            }
+            Err(e) => {
+                println!("Failed to load championship, using defaults: {:?}", e);
+                ChampionshipConfig::default()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundResult {
+    pub track: String,
+    pub order: Vec<String>,
+}
+
+/// Progress of a championship, saved after every round so it can be resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChampionshipState {
+    pub seed: u64, // the same seed gives the same cars in every round
+    pub round: usize,
+    pub points: HashMap<String, u32>,
+    pub rounds: Vec<RoundResult>,
+    pub finished: bool,
+}
+
+impl ChampionshipState {
+    /// Resumes the saved championship, or starts a new one when there is none or its round
+    /// is not on the calendar any more.
+    pub fn load_or_new(seed: u64, config: &ChampionshipConfig) -> ChampionshipState {
+        match ChampionshipState::load_json(STATE_FILE) {
+            Ok(state) if !state.finished && state.track(config).is_some() => {
+                println!("Resuming championship at round {}", state.round + 1);
+                state
+            }
+            Ok(state) if !state.finished => {
+                println!(
+                    "Round {} of the saved championship is not on the calendar, starting over",
+                    state.round + 1
+                );
+                ChampionshipState::new(seed)
+            }
+            _ => ChampionshipState::new(seed),
+        }
+    }
+
+    fn new(seed: u64) -> ChampionshipState {
+        ChampionshipState {
+            seed,
+            round: 0,
+            points: HashMap::new(),
+            rounds: vec![],
+            finished: false,
+        }
+    }
+
+    /// Track of the current round.
+    pub fn track<'a>(&self, config: &'a ChampionshipConfig) -> Option<&'a String> {
+        config.tracks.get(self.round)
+    }
+
+    pub fn standings(&self) -> Vec<(String, u32)> {
+        let mut rows = self
+            .points
+            .iter()
+            .map(|(driver, points)| (driver.clone(), *points))
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        rows
+    }
+
+    fn award(&mut self, result: &SessionResult, config: &ChampionshipConfig) {
+        for car in result.cars.iter() {
+            let points = if car.total_time.is_some() {
+                config.points.get(car.position - 1).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            *self.points.entry(car.driver.clone()).or_insert(0) += points;
+        }
+        self.rounds.push(RoundResult {
+            track: result.track.clone(),
+            order: result.cars.iter().map(|c| c.driver.clone()).collect(),
+        });
+        self.round += 1;
+        self.finished = self.round >= config.tracks.len();
+    }
+}
+
+/// Sent by race control when the results of a race are final.
+#[derive(Clone, Debug, Message)]
+pub struct RaceFinished(pub SessionResult);
+
+/// Sent by race control when a session starts from the main menu, with the championship its
+/// race is a round of, if any.
+#[derive(Clone, Debug, Message)]
+pub struct SetSeason(pub Option<(ChampionshipConfig, ChampionshipState)>);
+
+/// What the standings screen between rounds shows.
+#[derive(Debug, Clone, Component)]
+pub struct StandingsBoard {
+    pub visible: bool,
+    pub title: String,
+    pub rows: Vec<(String, u32)>,
+}
+
+pub struct Championship {
+    season: Option<(ChampionshipConfig, ChampionshipState)>, // none while racing single races
+    board: Entity,
+    intermission: Option<f32>,
+    controls: Controls,
+}
+
+impl System for Championship {
+    fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
+        if let Some(SetSeason(season)) = msg.downcast_ref::<SetSeason>() {
+            self.season = season.clone();
+            self.intermission = None;
+            if let Some(board) = world.component_mut::<StandingsBoard>(self.board) {
+                board.visible = false;
+            }
+        }
+        if let Some(RaceFinished(result)) = msg.downcast_ref::<RaceFinished>() {
+            let (config, state) = match self.season.as_mut() {
+                Some((config, state)) => (config, state),
+                None => return,
+            };
+            state.award(result, config);
+            if let Err(e) = state.save_json(STATE_FILE) {
+                println!("Failed to save championship: {:?}", e);
+            }
+
+            let rows = state.standings();
+            let title = if state.finished {
+                format!(
+                    "Champion: {}",
+                    rows.first().map(|r| r.0.as_str()).unwrap_or("-")
+                )
+            } else {
+                format!(
+                    "Standings after round {}/{}, next: {}",
+                    state.round,
+                    config.tracks.len(),
+                    state
+                        .track(config)
+                        .map(|t| Track::name(t))
+                        .unwrap_or_default()
+                )
+            };
+            let finished = state.finished;
+
+            let board = world.component_mut::<StandingsBoard>(self.board).unwrap();
+            board.visible = true;
+            board.title = title;
+            board.rows = rows;
+
+            if !finished {
+                self.intermission = Some(INTERMISSION);
+            }
+        }
+    }
+
+    fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) {
+            return;
+        }
+        if let Some(t) = self.intermission {
+            let t = t - delta_time.as_secs_f32();
+            if t > 0.0 {
+                self.intermission = Some(t);
+            } else {
+                self.intermission = None;
+                self.start_round(world);
+            }
+        }
+    }
+
+    fn window_event(&mut self, event: &GlutinWindowEvent, _world: &mut StaticWorld) -> bool {
+        if let Some((action, press)) = self.controls.action(event, None) {
+            if action == Action::Continue && !press && self.intermission.is_some() {
+                self.intermission = Some(0.0);
+            }
+        }
+
+        false
+    }
+}
+
+impl Championship {
+    pub fn new(
+        season: Option<(ChampionshipConfig, ChampionshipState)>,
+        controls: Controls,
+        world: &mut StaticWorld,
+    ) -> Championship {
+        let board = world.new_entity_with_component(StandingsBoard {
+            visible: false,
+            title: String::new(),
+            rows: vec![],
+        });
+
+        Championship {
+            season,
+            board,
+            intermission: None,
+            controls,
+        }
+    }
+
+    pub fn board(&self) -> Entity {
+        self.board
+    }
+
+    fn start_round(&mut self, world: &mut StaticWorld) {
+        let file = match self
+            .season
+            .as_ref()
+            .and_then(|(config, state)| state.track(config))
+        {
+            Some(file) => file.clone(),
+            None => return,
+        };
+
+        race_control::load_track(world, &file);
+
+        if let Some(board) = world.component_mut::<StandingsBoard>(self.board) {
+            board.visible = false;
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use crate::car::CarComponent;
+use crate::car_driver::Autopilot;
+use crate::menu;
+use crate::race_control::{NewSession, RacePhase, RaceStatus};
+use crate::results::SessionInfo;
+use crate::track::{LoadTrack, Track, TrackPath};
+
+extern crate serde;
+
+use glui::mecs::*;
+use glui::tools::serde_tools::{SerdeError, SerdeJsonQuick};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LEADERBOARD_DIR: &str = "leaderboards";
+const LEADERBOARD_SIZE: usize = 10;
+const DRIFT_SPEED: f32 = 10.0; // slowest speed a slide counts as a drift (m/s)
+const DRIFT_ANGLE: f32 = 0.25; // smallest slip angle counted as a drift (rad)
+const SPIN_ANGLE: f32 = 1.75; // slip angle past which the car has spun (rad)
+const SPIN_SPEED: f32 = 3.0; // slowest speed a spin is noticed at (m/s)
+const SUSTAIN_TIME: f32 = 0.5; // time a drift has to last to add to the multiplier (s)
+const CHAIN_TIME: f32 = 2.0; // gap allowed between drifts of one combo (s)
+const MAX_MULTIPLIER: usize = 10;
+const POINTS_SCALE: f32 = 10.0; // points per radian of slip angle times metre driven
+
+/// Drift points of a car, a combo is banked with its multiplier once no drift follows it.
+#[derive(Debug, Clone, Default, PartialEq, Component)]
+pub struct DriftScore {
+    pub score: f32,          // banked points
+    pub combo: f32,          // points of the open combo, before the multiplier
+    pub multiplier: usize,   // sustained drifts chained in the open combo
+    pub best_combo: f32,     // largest combo banked, with its multiplier
+    pub record: Option<f32>, // best score of the leaderboard of the track
+    pub last_event: Option<String>,
+    drift_time: f32,  // length of the current drift (s)
+    since_drift: f32, // time since the last drift ended (s)
+}
+
+impl DriftScore {
+    fn new(record: Option<f32>) -> DriftScore {
+        DriftScore {
+            record,
+            ..Default::default()
+        }
+    }
+
+    /// Points of the open combo with its multiplier.
+    pub fn pending(&self) -> f32 {
+        self.combo * self.multiplier as f32
+    }
+
+    fn drift(&mut self, slip_angle: f32, speed: f32, dt: f32) {
+        if self.drift_time < SUSTAIN_TIME && self.drift_time + dt >= SUSTAIN_TIME {
+            self.multiplier = (self.multiplier + 1).min(MAX_MULTIPLIER);
+        }
+        self.drift_time += dt;
+        self.since_drift = 0.0;
+        self.combo += slip_angle * speed * dt * POINTS_SCALE;
+    }
+
+    fn coast(&mut self, dt: f32) {
+        self.drift_time = 0.0;
+        self.since_drift += dt;
+        if self.since_drift > CHAIN_TIME {
+            self.bank();
+        }
+    }
+
+    fn bank(&mut self) {
+        if self.multiplier > 0 {
+            let points = self.pending();
+            self.score += points;
+            self.best_combo = self.best_combo.max(points);
+            self.last_event = Some(format!("+{:.0} (x{})", points, self.multiplier));
+        }
+        self.combo = 0.0;
+        self.multiplier = 0;
+    }
+
+    fn lose(&mut self, reason: &str) {
+        if self.multiplier > 0 {
+            self.last_event = Some(format!("Combo lost: {}", reason));
+        }
+        self.combo = 0.0;
+        self.multiplier = 0;
+        self.drift_time = 0.0;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub timestamp: u64, // seconds since the unix epoch
+    pub driver: String,
+    pub score: f32,
+    pub best_combo: f32,
+}
+
+/// Best drift scores set on a track.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub track: String,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+impl Leaderboard {
+    fn path(track: &str) -> String {
+        format!("{}/drift_{}.json", LEADERBOARD_DIR, track)
+    }
+
+    pub fn load(track: &str) -> Leaderboard {
+        Leaderboard::load_json(&Self::path(track)).unwrap_or_else(|_| Leaderboard {
+            track: track.to_owned(),
+            entries: vec![],
+        })
+    }
+
+    pub fn record(&self) -> Option<f32> {
+        self.entries.first().map(|e| e.score)
+    }
+
+    /// Adds the entry if it makes the board, returning its place.
+    pub fn insert(&mut self, entry: LeaderboardEntry) -> Option<usize> {
+        let place = self
+            .entries
+            .iter()
+            .position(|e| e.score < entry.score)
+            .unwrap_or(self.entries.len());
+        if place >= LEADERBOARD_SIZE {
+            return None;
+        }
+        self.entries.insert(place, entry);
+        self.entries.truncate(LEADERBOARD_SIZE);
+        Some(place)
+    }
+
+    pub fn save(&self) -> Result<String, SerdeError> {
+        if let Err(e) = fs::create_dir_all(LEADERBOARD_DIR) {
+            println!("Failed to create {}: {:?}", LEADERBOARD_DIR, e);
+        }
+        let path = Self::path(&self.track);
+        self.save_json(&path)?;
+        Ok(path)
+    }
+}
+
+/// Scores the drifts of the cars of the players and keeps the leaderboard of the track, in the
+/// modes scoring drifts.
+pub struct DriftSystem {
+    cars: Vec<Entity>,
+    session: SessionInfo,
+    path: TrackPath,
+    saved: Vec<bool>,
+    scoring: bool, // the session scores drifts
+}
+
+impl System for DriftSystem {
+    fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
+        if let Some(LoadTrack(file)) = msg.downcast_ref::<LoadTrack>() {
+            match Track::centreline(file) {
+                Ok(path) => {
+                    self.path = path;
+                    self.session.track = file.clone();
+                    self.reset(world);
+                }
+                Err(e) => println!("Failed to read path of {}: {:?}", file, e),
+            }
+        }
+        if let Some(session) = msg.downcast_ref::<NewSession>() {
+            self.scoring = session.mode.scores_drifts();
+        }
+    }
+
+    fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) || !self.scoring {
+            return;
+        }
+        let dt = delta_time.as_secs_f32();
+        let half_width = Track::track_width() / 2.0;
+
+        for (i, e) in self.cars.iter().copied().enumerate() {
+            // the autopilot of the demo does not score for the player
+            let car = match world.component::<CarComponent>(e) {
+                Some(car) if world.component::<Autopilot>(e).is_none() => *car,
+                _ => continue,
+            };
+            let (phase, finished, in_pit_lane) = world
+                .component::<RaceStatus>(e)
+                .map(|s| (s.phase, s.finish_time.is_some(), s.in_pit_lane))
+                .unwrap_or((RacePhase::Running, false, false));
+            let score = world.component_mut::<DriftScore>(e).unwrap();
+
+            if finished {
+                if !self.saved[i] {
+                    self.saved[i] = true;
+                    score.bank();
+                    let entry = LeaderboardEntry {
+                        timestamp: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs())
+                            .unwrap_or(0),
+                        driver: self.session.driver_name(i),
+                        score: score.score,
+                        best_combo: score.best_combo,
+                    };
+                    let mut board = Leaderboard::load(&Track::name(&self.session.track));
+                    if let Some(place) = board.insert(entry) {
+                        score.last_event = Some(format!("#{} on the leaderboard", place + 1));
+                        score.record = board.record();
+                        match board.save() {
+                            Ok(path) => println!("Drift score saved to {}", path),
+                            Err(e) => println!("Failed to save drift score: {:?}", e),
+                        }
+                    }
+                }
+                continue;
+            }
+            match phase {
+                RacePhase::Green | RacePhase::Running | RacePhase::Chequered => {}
+                _ => continue,
+            }
+            if car.airborne {
+                continue;
+            }
+
+            let speed = car.speed();
+            let slip_angle = if speed > 0.0 {
+                car.dir().dot(car.velocity.sgn()).clamp(-1.0, 1.0).acos()
+            } else {
+                0.0
+            };
+            let off_track = self.path.project(car.position).offset.abs() > half_width;
+
+            if speed > SPIN_SPEED && slip_angle > SPIN_ANGLE {
+                score.lose("spun");
+            } else if off_track && !in_pit_lane {
+                score.lose("off track");
+            } else if speed > DRIFT_SPEED && slip_angle > DRIFT_ANGLE {
+                score.drift(slip_angle, speed, dt);
+            } else {
+                score.coast(dt);
+            }
+        }
+    }
+}
+
+impl DriftSystem {
+    pub fn new(
+        cars: Vec<Entity>,
+        session: SessionInfo,
+        scoring: bool,
+        world: &mut StaticWorld,
+    ) -> DriftSystem {
+        let path = Track::centreline(&session.track).expect("Failed to read track!");
+        let mut drift = DriftSystem {
+            saved: vec![false; cars.len()],
+            cars,
+            session,
+            path,
+            scoring,
+        };
+        drift.reset(world);
+        drift
+    }
+
+    fn reset(&mut self, world: &mut StaticWorld) {
+        let record = Leaderboard::load(&Track::name(&self.session.track)).record();
+        for e in self.cars.iter().copied() {
+            if self.scoring {
+                world.add_component(e, DriftScore::new(record));
+            } else if world.component::<DriftScore>(e).is_some() {
+                world.remove_component::<DriftScore>(e);
+            }
+        }
+        self.saved = vec![false; self.cars.len()];
+    }
+}
+
+/// Entry point of `skyrace leaderboard`, prints the drift leaderboards of every track.
+pub fn print_leaderboards() {
+    let mut boards = vec![];
+    if let Ok(dir) = fs::read_dir(LEADERBOARD_DIR) {
+        for entry in dir.flatten() {
+            let path = entry.path();
+            if path.extension().map(|e| e == "json").unwrap_or(false) {
+                match Leaderboard::load_json(&path.to_string_lossy()) {
+                    Ok(board) => boards.push(board),
+                    Err(e) => println!("Skipping {}: {:?}", path.display(), e),
+                }
+            }
+        }
+    }
+    if boards.is_empty() {
+        println!("No drift scores in {}/ yet.", LEADERBOARD_DIR);
+        return;
+    }
+    boards.sort_by(|a, b| a.track.cmp(&b.track));
+
+    for (k, board) in boards.iter().enumerate() {
+        if k > 0 {
+            println!();
+        }
+        println!("Drift, {}", board.track);
+        println!(
+            "{:>3} {:<10} {:>10} {:>10}",
+            "", "Driver", "Score", "Best combo"
+        );
+        for (i, entry) in board.entries.iter().enumerate() {
+            println!(
+                "{:>3} {:<10} {:>10.0} {:>10.0}",
+                format!("{}.", i + 1),
+                entry.driver,
+                entry.score,
+                entry.best_combo
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn drive(score: &mut DriftScore, drifting: bool, time: f32) {
+        for _ in 0..(time / DT).round() as usize {
+            if drifting {
+                score.drift(0.5, 20.0, DT);
+            } else {
+                score.coast(DT);
+            }
+        }
+    }
+
+    #[test]
+    fn chained_drifts_multiply_the_combo() {
+        let mut score = DriftScore::default();
+        drive(&mut score, true, 1.0);
+        drive(&mut score, false, 1.0);
+        drive(&mut score, true, 1.0);
+        assert_eq!(score.multiplier, 2);
+        drive(&mut score, false, 3.0);
+        assert_eq!(score.multiplier, 0);
+        assert!((score.score - 2.0 * 0.5 * 20.0 * 2.0 * POINTS_SCALE).abs() < 1.0);
+    }
+
+    #[test]
+    fn flicks_and_lost_combos_score_nothing() {
+        let mut score = DriftScore::default();
+        drive(&mut score, true, 0.2);
+        drive(&mut score, false, 3.0);
+        assert_eq!(score.score, 0.0);
+
+        drive(&mut score, true, 1.0);
+        score.lose("spun");
+        drive(&mut score, false, 3.0);
+        assert_eq!(score.score, 0.0);
+    }
+
+    #[test]
+    fn leaderboard_keeps_the_best_scores_in_order() {
+        let mut board = Leaderboard {
+            track: "test".to_owned(),
+            entries: vec![],
+        };
+        let entry = |score: f32| LeaderboardEntry {
+            timestamp: 0,
+            driver: "Player".to_owned(),
+            score,
+            best_combo: 0.0,
+        };
+        for i in 0..LEADERBOARD_SIZE {
+            board.insert(entry(i as f32 * 100.0));
+        }
+        assert_eq!(board.insert(entry(-1.0)), None);
+        assert_eq!(board.insert(entry(450.0)), Some(5));
+        assert_eq!(board.entries.len(), LEADERBOARD_SIZE);
+        assert_eq!(board.record(), Some(900.0));
+    }
+}
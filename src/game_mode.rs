@@ -0,0 +1,246 @@
+use crate::race_control::{RaceRules, RaceStatus};
+
+extern crate serde;
+
+use serde::{Deserialize, Serialize};
+
+/// Rules played on top of the race, race control asks the mode who is out of the race and
+/// the game sets up the grid and the scoring the mode asks for.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    /// The first car to complete the laps wins.
+    #[default]
+    Race,
+    /// A single car sets lap times, offences invalidate the lap instead of adding penalties.
+    TimeTrial,
+    /// Each time the leader completes a lap the last car is taken off the track, until one is
+    /// left.
+    Elimination,
+    /// A single car races a countdown that checkpoints along the track extend.
+    TimeAttack,
+    /// A single car scores its drifts for the leaderboard of the track.
+    Drift,
+}
+
+/// Countdown of the time attack mode.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeAttackRules {
+    pub start_time: f32,    // s
+    pub checkpoints: usize, // per lap, evenly spaced with the last one on the start line
+    pub extension: f32,     // time added by a checkpoint (s)
+}
+
+impl Default for TimeAttackRules {
+    fn default() -> Self {
+        TimeAttackRules {
+            start_time: 30.0,
+            checkpoints: 4,
+            extension: 12.0,
+        }
+    }
+}
+
+impl GameMode {
+    /// The mode chosen by a command line argument.
+    pub fn from_arg(arg: &str) -> Option<GameMode> {
+        match arg {
+            "timetrial" => Some(GameMode::TimeTrial),
+            "elimination" => Some(GameMode::Elimination),
+            "timeattack" => Some(GameMode::TimeAttack),
+            "drift" => Some(GameMode::Drift),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Race => "Race",
+            GameMode::TimeTrial => "Time trial",
+            GameMode::Elimination => "Elimination",
+            GameMode::TimeAttack => "Time attack",
+            GameMode::Drift => "Drift",
+        }
+    }
+
+    /// AI cars lining up with the players when `asked` were asked for.
+    pub fn opponents(&self, asked: usize) -> usize {
+        match self {
+            GameMode::Race | GameMode::Elimination => asked,
+            _ => 0,
+        }
+    }
+
+    /// Whether offences invalidate the lap instead of adding penalties.
+    pub fn invalidates_laps(&self) -> bool {
+        *self == GameMode::TimeTrial
+    }
+
+    /// Whether the drifts of the cars are scored.
+    pub fn scores_drifts(&self) -> bool {
+        *self == GameMode::Drift
+    }
+
+    /// Whether the session can be a round of a championship.
+    pub fn in_championship(&self) -> bool {
+        self.opponents(1) > 0
+    }
+
+    /// Whether cars out of the race are taken off the track.
+    pub fn removes_cars(&self) -> bool {
+        *self == GameMode::Elimination
+    }
+
+    /// Laps of a race of the given number of cars.
+    pub fn race_laps(&self, laps: usize, cars: usize) -> usize {
+        match self {
+            GameMode::Elimination => cars.saturating_sub(1).max(1),
+            _ => laps,
+        }
+    }
+
+    /// Time a car starts the race with, if it races against the clock.
+    pub fn time_limit(&self, rules: &RaceRules) -> Option<f32> {
+        match self {
+            GameMode::TimeAttack => Some(rules.time_attack.start_time),
+            _ => None,
+        }
+    }
+
+    /// Applies the rules of the mode to a car still racing, returns whether it is out.
+    pub fn judge(&self, status: &mut RaceStatus, rules: &RaceRules, length: f32, dt: f32) -> bool {
+        match self {
+            GameMode::TimeAttack => {
+                let ta = rules.time_attack;
+                let spacing = length / ta.checkpoints.max(1) as f32;
+                let mut time_left = status.time_left.unwrap_or(0.0);
+                while status.progress >= (status.checkpoints + 1) as f32 * spacing {
+                    status.checkpoints += 1;
+                    time_left += ta.extension;
+                }
+                time_left -= dt;
+                status.time_left = Some(time_left.max(0.0));
+                time_left <= 0.0
+            }
+            _ => false,
+        }
+    }
+
+    /// Picks the car to take out of the race among the cars on track, given by their index
+    /// and status, and the number of cars already out.
+    pub fn eliminate(&self, cars: &[(usize, &RaceStatus)], out: usize) -> Option<usize> {
+        match self {
+            GameMode::Elimination => {
+                let leader_laps = cars.iter().map(|(_, s)| s.completed_laps()).max()?;
+                let left = cars.iter().filter(|(_, s)| s.eliminated.is_none()).count();
+                if leader_laps <= out || left < 2 {
+                    return None;
+                }
+                cars.iter()
+                    .filter(|(_, s)| s.eliminated.is_none() && s.finish_time.is_none())
+                    .min_by(|(_, a), (_, b)| a.progress.partial_cmp(&b.progress).unwrap())
+                    .map(|(i, _)| *i)
+            }
+            _ => None,
+        }
+    }
+
+    /// Lines of the HUD for a car at the given place in the running order.
+    pub fn hud(
+        &self,
+        status: &RaceStatus,
+        position: (usize, usize),
+    ) -> Vec<(&'static str, String)> {
+        match self {
+            GameMode::Race | GameMode::TimeTrial | GameMode::Drift => vec![],
+            GameMode::Elimination => {
+                let (place, cars) = position;
+                let state = if status.finish_time.is_none() && place == cars && cars > 1 {
+                    "LAST, out when the leader completes the lap".to_owned()
+                } else {
+                    format!("{} cars left", cars)
+                };
+                vec![("Elimination: ", state)]
+            }
+            GameMode::TimeAttack => {
+                let time = match (status.eliminated, status.time_left) {
+                    (Some(_), _) => "Time up".to_owned(),
+                    (None, Some(t)) => format!("{:.1} s", t),
+                    (None, None) => "-".to_owned(),
+                };
+                vec![
+                    ("Time left: ", time),
+                    ("Checkpoints: ", format!("{}", status.checkpoints)),
+                ]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::race_control::LapTime;
+    use glui::tools::Vec2;
+
+    const LENGTH: f32 = 1000.0;
+
+    fn rules(mode: GameMode) -> RaceRules {
+        RaceRules {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn single_car_modes_line_up_without_opponents() {
+        for arg in ["timetrial", "timeattack", "drift"].iter() {
+            let mode = GameMode::from_arg(arg).unwrap();
+            assert_eq!(mode.opponents(11), 0, "{}", arg);
+            assert!(!mode.in_championship(), "{}", arg);
+        }
+        assert_eq!(GameMode::Elimination.opponents(11), 11);
+        assert!(GameMode::TimeTrial.invalidates_laps());
+        assert!(GameMode::Drift.scores_drifts() && !GameMode::Race.scores_drifts());
+    }
+
+    #[test]
+    fn checkpoints_extend_the_countdown() {
+        let rules = rules(GameMode::TimeAttack);
+        let mut status = RaceStatus::new(0.0, Vec2::zero(), &rules, 1);
+        let start = rules.time_attack.start_time;
+
+        status.progress = LENGTH / 4.0 + 1.0;
+        assert!(!GameMode::TimeAttack.judge(&mut status, &rules, LENGTH, 1.0));
+        assert_eq!(status.checkpoints, 1);
+        assert_eq!(
+            status.time_left,
+            Some(start + rules.time_attack.extension - 1.0)
+        );
+
+        assert!(GameMode::TimeAttack.judge(&mut status, &rules, LENGTH, start + 20.0));
+        assert_eq!(status.time_left, Some(0.0));
+    }
+
+    #[test]
+    fn the_last_car_is_out_when_the_leader_completes_a_lap() {
+        let rules = rules(GameMode::Elimination);
+        let mut statuses = (0..3)
+            .map(|i| RaceStatus::new(i as f32 * 10.0, Vec2::zero(), &rules, 3))
+            .collect::<Vec<_>>();
+        assert_eq!(statuses[0].race_laps, 2);
+
+        let cars = statuses.iter().enumerate().collect::<Vec<_>>();
+        assert_eq!(GameMode::Elimination.eliminate(&cars, 0), None);
+
+        statuses[2].laps.push(LapTime {
+            time: 60.0,
+            valid: true,
+            tires: Default::default(),
+        });
+        let cars = statuses.iter().enumerate().collect::<Vec<_>>();
+        assert_eq!(GameMode::Elimination.eliminate(&cars, 0), Some(0));
+        assert_eq!(GameMode::Elimination.eliminate(&cars, 1), None);
+        assert_eq!(GameMode::Race.eliminate(&cars, 0), None);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::car::CarComponent;
+use crate::menu;
+
+extern crate serde;
+
+use gilrs::{Axis, Button, Gilrs};
+use glui::mecs::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How the sticks and triggers of gamepads are turned into driving input.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    pub first_player: usize, // driven by the first pad, the others drive the next players
+    pub deadzone: f32,       // travel of sticks and triggers ignored around rest
+    pub linearity: f32,      // exponent of the steering curve, 1 is linear
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        GamepadConfig {
+            first_player: 0,
+            deadzone: 0.1,
+            linearity: 1.5,
+        }
+    }
+}
+
+impl GamepadConfig {
+    fn shape(&self, x: f32, exponent: f32) -> f32 {
+        let travel = ((x.abs() - self.deadzone) / (1.0 - self.deadzone)).clamp(0.0, 1.0);
+        // rounded so that recordings only change when the input really does
+        (travel.powf(exponent) * x.signum() * 100.0).round() / 100.0
+    }
+}
+
+/// Analog input of the gamepad driving the car it is attached to.
+#[derive(Debug, Copy, Clone, Default, Component)]
+pub struct PadInput {
+    pub steer: f32,    // in [-1,1]
+    pub throttle: f32, // in [0,1]
+    pub brake: f32,    // in [0,1]
+    pub nitro: bool,
+}
+
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    cars: Vec<Entity>, // cars of the local players
+    config: GamepadConfig,
+}
+
+impl System for Gamepads {
+    fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
+        let gilrs = match self.gilrs.as_mut() {
+            Some(gilrs) => gilrs,
+            None => return,
+        };
+        while gilrs.next_event().is_some() {}
+        if menu::is_paused(world) {
+            return;
+        }
+
+        let mut connected = vec![false; self.cars.len()];
+        for (k, (_id, pad)) in gilrs.gamepads().enumerate() {
+            let player = self.config.first_player + k;
+            let car = match self.cars.get(player) {
+                Some(car) => *car,
+                None => continue,
+            };
+            connected[player] = true;
+
+            if world.component::<CarComponent>(car).is_none() {
+                continue;
+            }
+            let trigger = |b: Button| pad.button_data(b).map(|d| d.value()).unwrap_or(0.0);
+
+            let input = PadInput {
+                steer: self
+                    .config
+                    .shape(pad.value(Axis::LeftStickX), self.config.linearity),
+                throttle: self.config.shape(trigger(Button::RightTrigger2), 1.0),
+                brake: self.config.shape(trigger(Button::LeftTrigger2), 1.0),
+                nitro: pad.is_pressed(Button::South),
+            };
+
+            match world.component_mut::<PadInput>(car) {
+                Some(pad_input) => *pad_input = input,
+                None => world.add_component(car, input),
+            }
+        }
+
+        // unplugged pads leave the keyboard in control
+        for (player, car) in self.cars.iter().enumerate() {
+            if !connected[player] && world.component::<PadInput>(*car).is_some() {
+                world.remove_component::<PadInput>(*car);
+            }
+        }
+    }
+}
+
+impl Gamepads {
+    pub fn new(cars: Vec<Entity>, config: GamepadConfig) -> Gamepads {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(e) => {
+                println!("Gamepads are not available: {:?}", e);
+                None
+            }
+        };
+
+        Gamepads {
+            gilrs,
+            cars,
+            config,
+        }
+    }
+}
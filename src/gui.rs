@@ -1,4 +1,7 @@
-use crate::car::CarComponent;
+use crate::car::{CarComponent, Damage, TireState};
+use crate::championship::StandingsBoard;
+use crate::drift::DriftScore;
+use crate::race_control::{format_time, RacePhase, RaceStatus};
 use crate::track::{ShowWireframe, Track};
 use glui::gui::{
     gui_primitives, Align, Button, ButtonBckg, FontSize, GridLayout, GuiBuilder, GuiDimension,
@@ -15,17 +18,147 @@ pub struct Gui {
     pub speed: f32,
     pub throttle: f32,
     pub steer: f32,
+    pub boost: f32, // share of the nitro tank that is full
+    pub boosting: bool,
+    pub draft: f32,            // strength of the wake of a car ahead
+    pub tires: [TireState; 2], // front and rear
+    pub fuel: f32,             // kg
+    pub damage: Damage,
+    pub position: (usize, usize), // place in the running order and number of cars
+    pub pit: Option<String>,
+    pub pit_stops: usize,
+    pub assists: Vec<(String, bool)>, // configured aids and whether they are intervening
     pub car: Entity,
     pub wire_frame: bool,
+    pub laps: usize,
+    pub lap: usize,
+    pub last_lap: Option<(f32, bool)>,
+    pub best_lap: Option<f32>,
+    pub penalty_time: f32,
+    pub warnings: usize,
+    pub last_offence: Option<String>,
+    pub drift: Option<DriftScore>,
+    pub mode_lines: Vec<(&'static str, String)>, // what the game mode shows of the car
+    pub phase: RacePhase,
+    pub standings: Option<Entity>,
+    pub show_standings: bool,
+    pub standings_title: String,
+    pub standings_rows: Vec<(String, u32)>,
+    pub column: Option<(usize, usize)>, // (player, players) when the screen is shared
 }
 
 #[allow(unused_must_use)]
 impl GuiBuilder for Gui {
     fn build(&self) {
+        if self.show_standings {
+            self.build_standings();
+            return;
+        }
+
+        if let Some((player, players)) = self.column {
+            -GridLayout {
+                col_widths: vec![GuiDimension::Default; players],
+                ..Default::default()
+            } << {
+                for i in 0..players {
+                    if i == player {
+                        self.build_hud();
+                    } else {
+                        -SkipCell {};
+                    }
+                }
+            };
+        } else {
+            self.build_hud();
+        }
+    }
+
+    fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
+        let car = match world.component_mut::<CarComponent>(self.car) {
+            Some(car) => car,
+            None => {
+                // the game mode took the car off the track
+                self.speed = 0.0;
+                self.throttle = 0.0;
+                self.boosting = false;
+                self.last_offence = Some("Out of the race".to_owned());
+                return;
+            }
+        };
+
+        self.speed = car.speed();
+        self.throttle = car.throttle;
+        self.steer = car.wheel_turn();
+        self.boost = car.boost_level();
+        self.boosting = car.boosting;
+        self.draft = car.draft;
+        self.tires = car.tires;
+        self.fuel = car.fuel;
+        self.damage = car.damage;
+
+        let (config, active) = (car.config.assists, car.active_assists);
+        self.assists = [
+            ("ABS", config.abs, active.abs),
+            ("TC", config.traction_control, active.traction_control),
+            ("ESC", config.stability_control, active.stability_control),
+        ]
+        .iter()
+        .filter(|(_, on, _)| *on)
+        .map(|(name, _, active)| (name.to_string(), *active))
+        .collect();
+
+        if let Some(status) = world.component::<RaceStatus>(self.car) {
+            self.lap = status.completed_laps();
+            self.laps = status.race_laps;
+            self.last_lap = status.laps.last().map(|l| (l.time, l.valid));
+            self.best_lap = status.best_lap();
+            self.penalty_time = status.penalty_time;
+            self.warnings = status.warnings;
+            self.last_offence = status.offences.last().map(|o| o.describe());
+            self.phase = status.phase;
+            self.pit_stops = status.pit_stops.len();
+            self.pit = match status.pit_stop {
+                Some(t) => Some(format!("Service, {:.1} s", t)),
+                None if status.pit_limiter => Some("Speed limiter".to_owned()),
+                None if status.in_pit_lane => Some("In the pit lane".to_owned()),
+                None => None,
+            };
+
+            let cars = world.entities_having_component::<RaceStatus>();
+            let ahead = cars
+                .iter()
+                .filter(|e| **e != self.car)
+                .filter_map(|e| world.component::<RaceStatus>(*e))
+                .filter(|other| other.is_ahead_of(status))
+                .count();
+            self.position = (ahead + 1, cars.len());
+            self.mode_lines = status.mode.hud(status, self.position);
+        }
+
+        self.drift = world.component::<DriftScore>(self.car).cloned();
+
+        if let Some(board) = self
+            .standings
+            .and_then(|e| world.component::<StandingsBoard>(e))
+        {
+            self.show_standings = board.visible;
+            self.standings_title = board.title.clone();
+            self.standings_rows = board.rows.clone();
+        }
+    }
+}
+
+impl Gui {
+    #[allow(unused_must_use)]
+    fn build_hud(&self) {
         let mut disp = HashMap::new();
         disp.insert(
             "Speed: ",
-            format!("{} km/h", (self.speed * 3.6 * 10.0).round() / 10.0),
+            format!(
+                "{} km/h{}",
+                (self.speed * 3.6 * 10.0).round() / 10.0,
+                if self.draft > 0.0 { "  SLIPSTREAM" } else { "" }
+            ),
         );
         disp.insert(
             "Throttle: ",
@@ -35,6 +168,127 @@ impl GuiBuilder for Gui {
             "Steer: ",
             format!("{}°", (self.steer * 10.0).round() / 10.0),
         );
+        let cells = (self.boost * 10.0).round() as usize;
+        disp.insert(
+            "Nitro: ",
+            format!(
+                "{}{}",
+                (0..10)
+                    .map(|i| if i < cells { "●" } else { "○" })
+                    .collect::<String>(),
+                if self.boosting { "  BOOST" } else { "" }
+            ),
+        );
+        for (name, tires) in ["Front tires: ", "Rear tires: "]
+            .iter()
+            .zip(self.tires.iter())
+        {
+            disp.insert(
+                name,
+                format!(
+                    "{}°C, {}% worn",
+                    tires.temperature.round(),
+                    (tires.wear * 100.0).round()
+                ),
+            );
+        }
+        disp.insert("Fuel: ", format!("{:.1} kg", self.fuel));
+        if self.damage.worst() > 0.0 {
+            let d = self.damage;
+            disp.insert(
+                "Damage: ",
+                [
+                    ("front", d.front),
+                    ("rear", d.rear),
+                    ("left", d.left),
+                    ("right", d.right),
+                ]
+                .iter()
+                .filter(|(_, z)| *z > 0.0)
+                .map(|(name, z)| format!("{} {}%", name, (z * 100.0).round()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            );
+        }
+        disp.insert(
+            "Pit stops: ",
+            match &self.pit {
+                Some(pit) => format!("{} ({})", self.pit_stops, pit),
+                None => format!("{}", self.pit_stops),
+            },
+        );
+        if !self.assists.is_empty() {
+            disp.insert(
+                "Assists: ",
+                self.assists
+                    .iter()
+                    .map(|(name, active)| format!("{} {}", name, if *active { "●" } else { "○" }))
+                    .collect::<Vec<_>>()
+                    .join("  "),
+            );
+        }
+        let signal = match self.phase {
+            RacePhase::Grid => "Form the grid".to_owned(),
+            RacePhase::Countdown(n) => (0..5)
+                .map(|i| if i < n { "●" } else { "○" })
+                .collect::<Vec<_>>()
+                .join(" "),
+            RacePhase::Green => "GO!".to_owned(),
+            RacePhase::Running => "Racing".to_owned(),
+            RacePhase::Chequered => "Chequered flag".to_owned(),
+            RacePhase::CoolDown => "Cool-down lap".to_owned(),
+        };
+        disp.insert("Start: ", signal);
+        disp.insert(
+            "Position: ",
+            format!("{}/{}", self.position.0, self.position.1),
+        );
+        disp.insert(
+            "Lap: ",
+            format!("{}/{}", (self.lap + 1).min(self.laps), self.laps),
+        );
+        if let Some((t, valid)) = self.last_lap {
+            disp.insert(
+                "Last lap: ",
+                format!(
+                    "{}{}",
+                    format_time(t),
+                    if valid { "" } else { " (invalid)" }
+                ),
+            );
+        }
+        if let Some(t) = self.best_lap {
+            disp.insert("Best lap: ", format_time(t));
+        }
+        disp.insert(
+            "Penalties: ",
+            format!("+{} s, {} warning(s)", self.penalty_time, self.warnings),
+        );
+        if let Some(offence) = &self.last_offence {
+            disp.insert("Race control: ", offence.clone());
+        }
+        for (name, line) in self.mode_lines.iter() {
+            disp.insert(*name, line.clone());
+        }
+        if let Some(drift) = &self.drift {
+            disp.insert(
+                "Drift: ",
+                if drift.multiplier > 0 {
+                    format!(
+                        "{:.0}  +{:.0} x{}",
+                        drift.score, drift.combo, drift.multiplier
+                    )
+                } else {
+                    format!("{:.0}", drift.score)
+                },
+            );
+            if let Some(record) = drift.record {
+                disp.insert("Record: ", format!("{:.0}", record));
+            }
+            if let Some(event) = &drift.last_event {
+                disp.insert("Combo: ", event.clone());
+            }
+        }
 
         -GridLayout {
             row_heights: vec![GuiDimension::Default, GuiDimension::Units(16.0)],
@@ -103,23 +357,84 @@ impl GuiBuilder for Gui {
         }
     }
 
-    fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
-        let car = world.component_mut::<CarComponent>(self.car).unwrap();
-
-        self.speed = car.speed();
-        self.throttle = car.throttle;
-        self.steer = car.wheel_turn();
-    }
-}
-
-impl Gui {
-    pub fn from_car(car: Entity) -> Gui {
+    pub fn from_car(car: Entity, laps: usize) -> Gui {
         Gui {
             speed: 0.0,
             throttle: 0.0,
             steer: 0.0,
+            boost: 0.0,
+            boosting: false,
+            draft: 0.0,
+            tires: [TireState::default(); 2],
+            fuel: 0.0,
+            damage: Damage::default(),
+            position: (1, 1),
+            pit: None,
+            pit_stops: 0,
+            assists: vec![],
             car,
             wire_frame: false,
+            laps,
+            lap: 0,
+            last_lap: None,
+            best_lap: None,
+            penalty_time: 0.0,
+            warnings: 0,
+            last_offence: None,
+            drift: None,
+            mode_lines: vec![],
+            phase: RacePhase::Grid,
+            standings: None,
+            show_standings: false,
+            standings_title: String::new(),
+            standings_rows: vec![],
+            column: None,
         }
     }
+
+    /// Places the HUD in the column of the given player.
+    pub fn for_player(mut self, player: usize, players: usize) -> Gui {
+        self.column = Some((player, players));
+        self
+    }
+
+    pub fn with_standings(mut self, board: Entity) -> Gui {
+        self.standings = Some(board);
+        self
+    }
+
+    #[allow(unused_must_use)]
+    fn build_standings(&self) {
+        let cell = |text: String| Text {
+            text,
+            align: Align::left(),
+            color: Vec4::WHITE,
+            font_size: FontSize::Em(0.9),
+            ..Default::default()
+        };
+
+        -Overlay::from(Vec4::new(0.0, 0.0, 0.0, 0.6)) << {
+            -GridLayout {
+                row_heights: vec![GuiDimension::Units(32.0); self.standings_rows.len() + 2],
+                col_widths: vec![
+                    GuiDimension::Units(60.0),
+                    GuiDimension::Units(240.0),
+                    GuiDimension::Units(80.0),
+                ],
+                ..Default::default()
+            } << {
+                -SkipCell {};
+                -cell(self.standings_title.clone());
+                -SkipCell {};
+                for (i, (driver, points)) in self.standings_rows.iter().enumerate() {
+                    -cell(format!("{}.", i + 1));
+                    -cell(driver.clone());
+                    -cell(format!("{} pts", points));
+                }
+                -SkipCell {};
+                -cell("Press Enter to continue".to_owned());
+                -SkipCell {};
+            };
+        };
+    }
 }
@@ -0,0 +1,311 @@
+extern crate serde;
+
+use crate::gamepad::GamepadConfig;
+use glui::mecs::{GlutinElementState, GlutinKey, GlutinWindowEvent, StaticWorld};
+use glui::tools::serde_tools::SerdeJsonQuick;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const CONTROLS_FILE: &str = "controls.json";
+
+/// Everything a key can be bound to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    // driving, bound per player
+    Throttle,
+    Brake,
+    SteerLeft,
+    SteerRight,
+    Nitro,
+    ResetCar,
+    SavePath,
+    // shared by everyone
+    CycleView,
+    FreeView,
+    NextCar,
+    PreviousCar,
+    ResetAiCars,
+    ToggleMusic,
+    FastSun,
+    AddKeyframe,
+    RemoveKeyframe,
+    PlayCameraPath,
+    Continue,
+    Pause,
+}
+
+/// Key names of `controls.json` for each action, global ones and per player.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlsConfig {
+    pub global: HashMap<Action, Vec<String>>,
+    pub players: Vec<HashMap<Action, Vec<String>>>,
+    pub gamepad: GamepadConfig,
+}
+
+impl Default for ControlsConfig {
+    fn default() -> Self {
+        let bind = |pairs: &[(Action, &[&str])]| {
+            pairs
+                .iter()
+                .map(|(a, keys)| (*a, keys.iter().map(|k| k.to_string()).collect()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        ControlsConfig {
+            global: bind(&[
+                (Action::CycleView, &["T"]),
+                (Action::FreeView, &["Y"]),
+                (Action::NextCar, &["LBracket"]),
+                (Action::PreviousCar, &["RBracket"]),
+                (Action::ResetAiCars, &["F5"]),
+                (Action::ToggleMusic, &["M"]),
+                (Action::FastSun, &["O"]),
+                (Action::AddKeyframe, &["C"]),
+                (Action::RemoveKeyframe, &["X"]),
+                (Action::PlayCameraPath, &["V"]),
+                (Action::Continue, &["Return"]),
+                (Action::Pause, &["Escape"]),
+            ]),
+            players: vec![
+                bind(&[
+                    (Action::Throttle, &["W"]),
+                    (Action::Brake, &["S"]),
+                    (Action::SteerLeft, &["A"]),
+                    (Action::SteerRight, &["D"]),
+                    (Action::Nitro, &["K"]),
+                    (Action::ResetCar, &["R"]),
+                    (Action::SavePath, &["P"]),
+                ]),
+                bind(&[
+                    (Action::Throttle, &["Up"]),
+                    (Action::Brake, &["Down"]),
+                    (Action::SteerLeft, &["Left"]),
+                    (Action::SteerRight, &["Right"]),
+                    (Action::Nitro, &["RControl"]),
+                    (Action::ResetCar, &["Back"]),
+                    (Action::SavePath, &["Slash"]),
+                ]),
+            ],
+            gamepad: GamepadConfig::default(),
+        }
+    }
+}
+
+/// Resolved key bindings, systems ask these for actions instead of looking at keys.
+#[derive(Debug, Clone)]
+pub struct Controls {
+    bindings: HashMap<GlutinKey, (Option<usize>, Action)>, // key to (player, action)
+    pub gamepad: GamepadConfig,
+}
+
+impl Controls {
+    pub fn load() -> Controls {
+        let config = match ControlsConfig::load_json(CONTROLS_FILE) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Failed to load controls, using defaults: {:?}", e);
+                ControlsConfig::default()
+            }
+        };
+
+        let (controls, problems) = Controls::from_config(&config);
+        for problem in problems.iter() {
+            println!("{}: {}", CONTROLS_FILE, problem);
+        }
+        controls
+    }
+
+    /// Builds the bindings, the first binding of a key wins, the problems found are returned.
+    pub fn from_config(config: &ControlsConfig) -> (Controls, Vec<String>) {
+        let mut bindings = HashMap::new();
+        let mut problems = vec![];
+
+        let scopes = std::iter::once((None, &config.global))
+            .chain(config.players.iter().enumerate().map(|(i, b)| (Some(i), b)));
+
+        for (player, actions) in scopes {
+            let mut actions = actions.iter().collect::<Vec<_>>();
+            actions.sort_by_key(|(a, _)| format!("{:?}", a));
+
+            for (action, keys) in actions {
+                for name in keys.iter() {
+                    let key = match key_from_name(name) {
+                        Some(key) => key,
+                        None => {
+                            problems.push(format!("unknown key {} for {:?}", name, action));
+                            continue;
+                        }
+                    };
+                    match bindings.get(&key) {
+                        Some((p, a)) if (*p, *a) != (player, *action) => problems.push(format!(
+                            "{} is bound to both {} and {}, ignoring the latter",
+                            name,
+                            describe(*p, *a),
+                            describe(player, *action)
+                        )),
+                        _ => {
+                            bindings.insert(key, (player, *action));
+                        }
+                    }
+                }
+            }
+        }
+
+        (
+            Controls {
+                bindings,
+                gamepad: config.gamepad,
+            },
+            problems,
+        )
+    }
+
+    /// The action of a key event for the given player (None for global actions)
+    /// and whether the key went down.
+    pub fn action(
+        &self,
+        event: &GlutinWindowEvent,
+        player: Option<usize>,
+    ) -> Option<(Action, bool)> {
+        if let GlutinWindowEvent::KeyboardInput { input, .. } = event {
+            let press = input.state == GlutinElementState::Pressed;
+            let key = input.virtual_keycode?;
+            match self.bindings.get(&key) {
+                Some((p, action)) if *p == player => Some((*action, press)),
+                _ => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    pub fn is_held(&self, world: &StaticWorld, action: Action, player: Option<usize>) -> bool {
+        self.bindings
+            .iter()
+            .any(|(key, binding)| *binding == (player, action) && world.is_key_pressed(*key))
+    }
+}
+
+fn describe(player: Option<usize>, action: Action) -> String {
+    match player {
+        Some(p) => format!("{:?} of player {}", action, p + 1),
+        None => format!("{:?}", action),
+    }
+}
+
+fn key_from_name(name: &str) -> Option<GlutinKey> {
+    use GlutinKey::*;
+
+    let key = match name {
+        "A" => A,
+        "B" => B,
+        "C" => C,
+        "D" => D,
+        "E" => E,
+        "F" => F,
+        "G" => G,
+        "H" => H,
+        "I" => I,
+        "J" => J,
+        "K" => K,
+        "L" => L,
+        "M" => M,
+        "N" => N,
+        "O" => O,
+        "P" => P,
+        "Q" => Q,
+        "R" => R,
+        "S" => S,
+        "T" => T,
+        "U" => U,
+        "V" => V,
+        "W" => W,
+        "X" => X,
+        "Y" => Y,
+        "Z" => Z,
+        "Key0" => Key0,
+        "Key1" => Key1,
+        "Key2" => Key2,
+        "Key3" => Key3,
+        "Key4" => Key4,
+        "Key5" => Key5,
+        "Key6" => Key6,
+        "Key7" => Key7,
+        "Key8" => Key8,
+        "Key9" => Key9,
+        "F1" => F1,
+        "F2" => F2,
+        "F3" => F3,
+        "F4" => F4,
+        "F5" => F5,
+        "F6" => F6,
+        "F7" => F7,
+        "F8" => F8,
+        "F9" => F9,
+        "F10" => F10,
+        "F11" => F11,
+        "F12" => F12,
+        "Escape" => Escape,
+        "Space" => Space,
+        "Return" => Return,
+        "Back" => Back,
+        "Tab" => Tab,
+        "Left" => Left,
+        "Right" => Right,
+        "Up" => Up,
+        "Down" => Down,
+        "LShift" => LShift,
+        "RShift" => RShift,
+        "LControl" => LControl,
+        "RControl" => RControl,
+        "LAlt" => LAlt,
+        "RAlt" => RAlt,
+        "LBracket" => LBracket,
+        "RBracket" => RBracket,
+        "Comma" => Comma,
+        "Period" => Period,
+        "Slash" => Slash,
+        "Semicolon" => Semicolon,
+        "Apostrophe" => Apostrophe,
+        "Minus" => Minus,
+        "Equals" => Equals,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_default_bindings_are_clean() {
+        let (controls, problems) = Controls::from_config(&ControlsConfig::default());
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(
+            controls.bindings.get(&GlutinKey::W),
+            Some(&(Some(0), Action::Throttle))
+        );
+        assert_eq!(
+            controls.bindings.get(&GlutinKey::Escape),
+            Some(&(None, Action::Pause))
+        );
+    }
+
+    #[test]
+    fn a_key_bound_twice_keeps_its_first_binding() {
+        let mut config = ControlsConfig::default();
+        config.players[1].insert(Action::Nitro, vec!["W".to_owned()]);
+
+        let (controls, problems) = Controls::from_config(&config);
+        assert_eq!(
+            problems,
+            vec!["W is bound to both Throttle of player 1 and Nitro of player 2, ignoring the latter"]
+        );
+        assert_eq!(
+            controls.bindings.get(&GlutinKey::W),
+            Some(&(Some(0), Action::Throttle))
+        );
+    }
+}
@@ -1,17 +1,29 @@
 mod ai_driver;
 mod cacti;
 mod camera_on_car;
+mod camera_path;
+mod championship;
+mod drift;
+mod game_mode;
+mod menu;
 mod playback_driver;
+mod race_control;
+mod results;
 mod sounds;
 mod sun_mover;
 mod terrain;
+mod tire_model;
 mod track;
+mod track_check;
+mod tv_director;
 mod utilities;
 mod wheel_mark;
 
 mod car;
 mod car_driver;
+mod gamepad;
 mod gui;
+mod input;
 mod line_system;
 mod sky;
 
@@ -20,16 +32,24 @@ extern crate glui_proc;
 extern crate noise;
 
 use crate::ai_driver::AiDriver;
-use crate::camera_on_car::CamFollowCar;
-use crate::car::CarSystem;
-use crate::car_driver::CarDriver;
+use crate::camera_on_car::{CamFollowCar, CameraSetup, View};
+use crate::car::{CarPresets, CarSpec, CarSystem, TireConfig};
+use crate::car_driver::{Autopilot, CarDriver};
+use crate::championship::{Championship, ChampionshipConfig, ChampionshipState};
+use crate::drift::DriftSystem;
+use crate::game_mode::GameMode;
+use crate::gamepad::Gamepads;
 use crate::gui::Gui;
+use crate::input::Controls;
 use crate::line_system::LineSystem;
+use crate::menu::{MenuChoice, MenuScreen, Menus};
+use crate::race_control::{RaceControl, RaceRules};
+use crate::results::SessionInfo;
 // use crate::playback_driver::{CarDriveState, PlaybackDriver};
 use crate::sky::Sky;
 use crate::sun_mover::SunMover;
 use crate::terrain::Terrain;
-use crate::track::Track;
+use crate::track::{Track, TRACK_FILE};
 use glui::mecs::World;
 use glui::mecs::*;
 // use glui::tools::serde_tools::SerdeJsonQuick;
@@ -38,33 +58,177 @@ use crate::sounds::Sounds;
 use crate::wheel_mark::WheelMark;
 use glui::tools::*;
 use rand::distributions::{Distribution, Uniform};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
 use std::env;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const GRID_SIZE: usize = 12;
+const PLAYER_SLOT: usize = 6;
+
+// the number given to a switch, a bad one is reported and left out for the default to stand
+fn parse_arg<T: FromStr>(arg: &str, value: &str) -> Option<T> {
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            println!("Ignoring {}, {} is not a valid number", arg, value);
+            None
+        }
+    }
+}
 
 fn main() {
     let mut follow_ai = true;
     let mut muted = false;
-    for arg in env::args() {
+    let mut mode = None;
+    let mut championship = false;
+    let mut split_screen = false;
+    let mut track_file = TRACK_FILE.to_owned();
+    let mut preset = None;
+    let mut paint = 0;
+    let mut opponents = GRID_SIZE;
+    let mut laps = None;
+    let mut damage = true;
+    // without a switch picking the session the main menu is shown over the demo
+    let mut in_race = false;
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("check-track") {
+        let path = args.get(2).map(|a| a.as_str()).unwrap_or(TRACK_FILE);
+        std::process::exit(track_check::run(path));
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("results") {
+        results::print_history();
+        return;
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("leaderboard") {
+        drift::print_leaderboards();
+        return;
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("tire-curves") {
+        let arg = |i: usize, name: &str, default: f32| {
+            args.get(i)
+                .and_then(|a| parse_arg(name, a))
+                .unwrap_or(default)
+        };
+        tire_model::print_curves(
+            &TireConfig::load().model,
+            arg(2, "stiffness", 14.0),
+            arg(3, "grip", 5.0),
+        );
+        return;
+    }
+
+    let mut seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    for arg in args {
         if arg == "race" {
             follow_ai = !follow_ai;
+            in_race = true;
         }
         if arg == "mute" {
             muted = !muted;
         }
+        if let Some(s) = arg.strip_prefix("seed=") {
+            seed = parse_arg(&arg, s).unwrap_or(seed);
+        }
+        if let Some(s) = arg.strip_prefix("track=") {
+            track_file = s.to_owned();
+            in_race = true;
+        }
+        if let Some(s) = arg.strip_prefix("car=") {
+            preset = parse_arg(&arg, s).or(preset);
+        }
+        if let Some(s) = arg.strip_prefix("paint=") {
+            paint = parse_arg(&arg, s).unwrap_or(paint);
+        }
+        if let Some(s) = arg.strip_prefix("opponents=") {
+            opponents = parse_arg(&arg, s).unwrap_or(opponents);
+        }
+        if let Some(s) = arg.strip_prefix("laps=") {
+            laps = parse_arg(&arg, s).or(laps);
+        }
+        if arg == "nodamage" {
+            damage = false;
+        }
+        if let Some(m) = GameMode::from_arg(&arg) {
+            mode = Some(m);
+            in_race = true;
+        }
+        if arg == "splitscreen" {
+            split_screen = true;
+            in_race = true;
+        }
+        if arg == "championship" {
+            championship = true;
+            in_race = true;
+        }
+    }
+    // these sessions are driven by the player whatever `race` asked for
+    if mode.is_some() || split_screen || championship {
+        follow_ai = false;
+    }
+
+    let controls = Controls::load();
+    let presets = CarPresets::load();
+    let tires = TireConfig::load();
+    let mut rules = RaceRules::load();
+    rules.damage &= damage;
+    if let Some(laps) = laps {
+        rules.laps = laps;
+    }
+    if let Some(mode) = mode {
+        rules.mode = mode;
+    }
+
+    let mut season = None;
+    if championship && rules.mode.in_championship() {
+        let config = ChampionshipConfig::load();
+        let state = ChampionshipState::load_or_new(seed, &config);
+        match state.track(&config) {
+            Some(track) => {
+                seed = state.seed;
+                rules.laps = config.laps;
+                track_file = track.clone();
+                season = Some((config, state));
+            }
+            None => println!("The championship has no tracks, racing a single race"),
+        }
     }
 
     let mut w: World = World::new_win(Vec2::new(1024.0, 768.0), "Skyracer", Vec3::grey(0.1));
 
-    let ds = DrawSystem::new(&mut w, NoController {});
-    let camera_entity = ds.camera_entity;
-    ds.camera_mut(w.as_static_mut()).params.zfar = 6000.0;
+    // in the demo the AI drives the car of the player until a session is started from the menu
+    let players = if split_screen { 2 } else { 1 };
 
-    w.add_system(ds);
+    // every local player looks through a camera of their own, side by side like the HUDs
+    let views = players;
+    let mut cameras = vec![];
+    for view in 0..views {
+        let mut ds = DrawSystem::new(&mut w, NoController {});
+        if views > 1 {
+            ds.set_viewport(camera_on_car::viewport(view, views));
+        }
+        cameras.push(ds.camera_entity);
+        ds.camera_mut(w.as_static_mut()).params.zfar = 6000.0;
+
+        w.add_system(ds);
+    }
 
-    let carsys = CarSystem::new();
     let linesys = LineSystem::new(w.as_static_mut());
     w.add_system(linesys);
 
-    let terrain = Terrain::new(w.as_static_mut());
+    // the ground is flat under every track the menu and the championship can race on
+    let mut tracks = menu::track_files();
+    if !tracks.contains(&track_file) {
+        tracks.push(track_file.clone());
+    }
+    let terrain = Terrain::new(w.as_static_mut(), &tracks);
+    let heights = terrain.height_map();
+    let carsys = CarSystem::new(rules.damage).with_terrain(heights.clone());
     let cacti = Cacti::new(w.as_static_mut(), terrain.height_tex());
     w.add_system(terrain);
     w.add_system(cacti);
@@ -77,69 +241,147 @@ fn main() {
 
     let sun_dir = Vec3::new(-1.0, 0.3, -1.0).sgn();
 
-    let track = Track::new(w.as_static_mut()).expect("Failed to init track!");
+    let track = Track::new(w.as_static_mut(), &track_file).expect("Failed to init track!");
+    let path = Track::centreline(&track_file).expect("Failed to read track!");
 
     let mut cars = vec![];
+    let mut grid = vec![];
+    let mut rng = XorShiftRng::seed_from_u64(seed);
 
-    if !follow_ai {
-        let state = (
-            -5.39,
-            Vec2::new(473.0, 184.0) * 0.8 + Vec2::new(-0.77907276, 0.6269335) * (2.2 * 1.5 - 1.0)
-                - Vec2::new(0.6269335, 0.77907276) * 9.0 * 2.0,
-        );
+    for player in 0..players {
         let car = carsys.create_car(
             w.as_static_mut(),
-            state,
-            Vec4::new(0.5333, 0.2014, 0.0314, 1.0),
-            0.05,
+            path.grid_slot(PLAYER_SLOT + player),
+            CarSpec {
+                color: menu::car_color(paint + player),
+                assists: presets.get(preset.unwrap_or(presets.player)).assists,
+                tires,
+                randomness: 0.05,
+            },
+            &mut rng,
         );
-        let driver = CarDriver::new(car);
+        let driver = CarDriver::new(car, player, controls.clone());
         w.add_system(driver);
+        if follow_ai {
+            w.as_static_mut().add_component(car, Autopilot);
+            let autopilot = AiDriver::new(
+                car,
+                Track::grid_lane(PLAYER_SLOT + player),
+                &track_file,
+                controls.clone(),
+                &mut rng,
+            )
+            .expect("Failed to read path!")
+            .for_autopilot();
+            w.add_system(autopilot);
+        }
 
         cars.push(car);
+        grid.push(PLAYER_SLOT + player);
     }
 
-
-    let mut rng = rand::thread_rng();
+    // the demo fills the grid, the menu can start a session with any number of opponents
+    let field = if follow_ai {
+        GRID_SIZE
+    } else {
+        players + rules.mode.opponents(opponents)
+    };
     let distr = Uniform::new(0.0, 1.0);
-    for j in [-5.4, -2.2, 2.2, 5.4].iter() {
-        for i in [0, 2, 4].iter() {
-            let state = (
-                -5.39,
-                Vec2::new(473.0, 184.0) * 0.8
-                    + Vec2::new(-0.77907276, 0.6269335) * (*j * 1.5 - 1.0)
-                    - Vec2::new(0.6269335, 0.77907276) * 9.0 * *i,
-            );
-            if *i != 2 || *j != 2.2 || follow_ai {
-                let car = carsys.create_car(
-                    w.as_static_mut(),
-                    state,
-                    Vec4::new(
+    for slot in 0..GRID_SIZE {
+        if !(PLAYER_SLOT..PLAYER_SLOT + players).contains(&slot) && cars.len() < field {
+            let car = carsys.create_car(
+                w.as_static_mut(),
+                path.grid_slot(slot),
+                CarSpec {
+                    color: Vec4::new(
                         distr.sample(&mut rng),
                         distr.sample(&mut rng),
                         distr.sample(&mut rng),
                         1.0,
                     ),
-                    0.1,
-                );
-                let driver = AiDriver::new(car, j / 2.0).expect("Failed to read path!");
-                w.add_system(driver);
-                cars.push(car);
-            }
+                    assists: presets.get(slot).assists,
+                    tires,
+                    randomness: 0.1,
+                },
+                &mut rng,
+            );
+            let driver = AiDriver::new(
+                car,
+                Track::grid_lane(slot),
+                &track_file,
+                controls.clone(),
+                &mut rng,
+            )
+            .expect("Failed to read path!");
+            w.add_system(driver);
+            cars.push(car);
+            grid.push(slot);
         }
     }
 
-    let sounds = Sounds::new(cars.clone(), camera_entity, muted);
+    let gamepads = Gamepads::new(cars[..players].to_vec(), controls.gamepad);
+    w.add_system(gamepads);
+
+    let mut sounds = Sounds::new(cars.clone(), cameras[0], muted, controls.clone());
+    if players > 1 {
+        sounds = sounds.with_listeners(cars[..players].to_vec());
+    }
     w.add_system(sounds);
 
     let marks = WheelMark::new(cars.clone(), w.as_static_mut());
     w.add_system(marks);
 
-    w.add_gui(Gui::from_car(cars[0]));
-    let follower = CamFollowCar::new(cars, camera_entity, false, w.as_static_mut());
-    w.add_system(follower);
-    
-    
+    // a championship can also be started from the main menu
+    let championship = Championship::new(season, controls.clone(), w.as_static_mut());
+    let board = championship.board();
+    w.add_system(championship);
+    for (player, car) in cars.iter().copied().enumerate().take(players) {
+        let mut gui = Gui::from_car(car, rules.laps).with_standings(board);
+        if players > 1 {
+            gui = gui.for_player(player, players);
+        }
+        w.add_gui(gui);
+    }
+
+    let session = SessionInfo {
+        track: track_file.clone(),
+        seed,
+        human_cars: players,
+        grid,
+    };
+    let scoring = DriftSystem::new(
+        cars.clone(),
+        session.clone(),
+        rules.mode.scores_drifts(),
+        w.as_static_mut(),
+    );
+    w.add_system(scoring);
+    let race_control = RaceControl::new(cars.clone(), session, rules, w.as_static_mut());
+    w.add_system(race_control);
+
+    let menus = Menus::new(in_race, controls.clone(), w.as_static_mut());
+    w.add_gui(MenuScreen::new(
+        menus.state(),
+        MenuChoice::new(&rules, &presets),
+        tracks,
+        presets,
+    ));
+    w.add_system(menus);
+
+    let view = if follow_ai { View::Tv } else { View::Racer };
+    for (player, camera) in cameras.into_iter().enumerate() {
+        let setup = CameraSetup {
+            car: player,
+            players: views,
+            view,
+            free_view: false,
+            track_file: track_file.clone(),
+            heights: heights.clone(),
+            controls: controls.clone(),
+        };
+        let follower = CamFollowCar::new(cars.clone(), camera, setup, w.as_static_mut());
+        w.add_system(follower);
+    }
 
     let sky = Sky::new(sun_dir, &mut w);
 
@@ -147,7 +389,7 @@ fn main() {
     w.add_system(track);
     w.add_system(carsys);
 
-    let sun_dir_setter = SunMover::new();
+    let sun_dir_setter = SunMover::new(controls);
     w.add_system(sun_dir_setter);
 
     w.run();
@@ -0,0 +1,404 @@
+use crate::car::CarPresets;
+use crate::championship::ChampionshipConfig;
+use crate::game_mode::GameMode;
+use crate::input::{Action, Controls};
+use crate::race_control::{NewSession, RaceControl, RaceRules, RestartRace};
+use crate::track::{Track, TRACK_FILE};
+use glui::gui::{
+    Align, Button, ButtonBckg, FontSize, GridLayout, GuiBuilder, GuiDimension, Overlay, SkipCell,
+    Text,
+};
+use glui::mecs::*;
+use glui::tools::Vec4;
+use std::process;
+use std::time::Duration;
+
+const MAX_OPPONENTS: usize = 11;
+const MAX_LAPS: usize = 10;
+
+/// Modes of the main menu with the switch starting them from the command line, the demo runs
+/// without one.
+const MODES: [(&str, Option<&str>); 7] = [
+    ("Demo", None),
+    ("Race", Some("race")),
+    ("Time trial", Some("timetrial")),
+    ("Championship", Some("championship")),
+    ("Drift", Some("drift")),
+    ("Elimination", Some("elimination")),
+    ("Time attack", Some("timeattack")),
+];
+
+/// Paint of the cars to choose from, the second player drives the next one.
+pub const CAR_COLORS: [(&str, [f32; 3]); 5] = [
+    ("Orange", [0.5333, 0.2014, 0.0314]),
+    ("Blue", [0.0314, 0.2014, 0.5333]),
+    ("Green", [0.0314, 0.4, 0.1]),
+    ("Red", [0.6, 0.02, 0.02]),
+    ("Silver", [0.55, 0.55, 0.6]),
+];
+
+pub fn car_color(car: usize) -> Vec4 {
+    let [r, g, b] = CAR_COLORS[car % CAR_COLORS.len()].1;
+    Vec4::new(r, g, b, 1.0)
+}
+
+/// What the buttons of the menus ask for.
+#[derive(Debug, Clone, Message)]
+pub enum MenuCommand {
+    Launch(NewSession),
+    Resume,
+    Restart, // the same race again, with the same cars
+
+    Quit,
+}
+
+/// Which menu is on screen.
+#[derive(Debug, Copy, Clone, PartialEq, Component)]
+pub struct MenuState {
+    pub main: bool,
+    pub paused: bool,
+}
+
+/// Whether the game is paused, the systems moving it on in time stand still until it resumes.
+pub fn is_paused(world: &StaticWorld) -> bool {
+    world
+        .entities_having_component::<MenuState>()
+        .iter()
+        .any(|e| {
+            world
+                .component::<MenuState>(*e)
+                .is_some_and(|state| state.paused)
+        })
+}
+
+/// Settings picked in the main menu.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuChoice {
+    pub mode: usize, // in MODES
+    pub track: usize,
+    pub car: usize,   // in the car presets
+    pub paint: usize, // in CAR_COLORS
+    pub opponents: usize,
+    pub sound: bool,
+    pub laps: usize,
+    pub damage: bool,
+}
+
+impl MenuChoice {
+    pub fn new(rules: &RaceRules, presets: &CarPresets) -> MenuChoice {
+        MenuChoice {
+            mode: 1,
+            track: 0,
+            car: presets.player % presets.cars.len().max(1),
+            paint: 0,
+            opponents: MAX_OPPONENTS,
+            sound: true,
+            laps: rules.laps.clamp(1, MAX_LAPS),
+            damage: rules.damage,
+        }
+    }
+
+    /// The chosen session, None for the demo.
+    pub fn session(&self, tracks: &[String], presets: &CarPresets) -> Option<NewSession> {
+        let switch = MODES[self.mode].1?;
+        Some(NewSession {
+            mode: GameMode::from_arg(switch).unwrap_or_default(),
+            championship: switch == "championship",
+            track: tracks
+                .get(self.track)
+                .cloned()
+                .unwrap_or_else(|| TRACK_FILE.to_owned()),
+            laps: self.laps,
+            damage: self.damage,
+            opponents: self.opponents,
+            assists: presets.get(self.car).assists,
+            paint: self.paint,
+            sound: self.sound,
+        })
+    }
+}
+
+/// Tracks offered by the main menu, the default one and those of the championship.
+pub fn track_files() -> Vec<String> {
+    let mut tracks = vec![TRACK_FILE.to_owned()];
+    for track in ChampionshipConfig::load().tracks {
+        if !tracks.contains(&track) {
+            tracks.push(track);
+        }
+    }
+    tracks
+}
+
+/// Opens the main menu or pauses the race with the pause key and carries out what the menus
+/// ask for.
+pub struct Menus {
+    state: Entity,
+    in_race: bool, // launched into a race rather than the demo behind the main menu
+    controls: Controls,
+}
+
+impl System for Menus {
+    fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
+        if let Some(command) = msg.downcast_ref::<MenuCommand>() {
+            match command {
+                MenuCommand::Launch(session) => {
+                    // from now on the pause key pauses the race
+                    world.send_by_type::<RaceControl, _>(session.clone());
+                    self.in_race = true;
+                    self.set_state(world, false, false);
+                }
+                MenuCommand::Resume => self.set_state(world, false, false),
+                MenuCommand::Restart => {
+                    world.send_by_type::<RaceControl, _>(RestartRace);
+                    self.set_state(world, false, false);
+                }
+                MenuCommand::Quit => process::exit(0),
+            }
+        }
+    }
+
+    fn window_event(&mut self, event: &GlutinWindowEvent, world: &mut StaticWorld) -> bool {
+        if let Some((action, press)) = self.controls.action(event, None) {
+            if action == Action::Pause && !press {
+                let state = *world.component::<MenuState>(self.state).unwrap();
+                if self.in_race {
+                    self.set_state(world, false, !state.paused);
+                } else {
+                    self.set_state(world, !state.main, false);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl Menus {
+    /// The main menu is shown over the demo unless the game was launched into a race.
+    pub fn new(in_race: bool, controls: Controls, world: &mut StaticWorld) -> Menus {
+        let state = world.new_entity_with_component(MenuState {
+            main: !in_race,
+            paused: false,
+        });
+
+        Menus {
+            state,
+            in_race,
+            controls,
+        }
+    }
+
+    pub fn state(&self) -> Entity {
+        self.state
+    }
+
+    fn set_state(&mut self, world: &mut StaticWorld, main: bool, paused: bool) {
+        let state = world.component_mut::<MenuState>(self.state).unwrap();
+        state.main = main;
+        state.paused = paused;
+    }
+}
+
+/// The main menu and the pause menu.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MenuScreen {
+    pub state: Entity,
+    pub visible: MenuState,
+    pub choice: MenuChoice,
+    pub tracks: Vec<String>,
+    pub presets: CarPresets,
+}
+
+#[allow(unused_must_use)]
+impl GuiBuilder for MenuScreen {
+    fn build(&self) {
+        if self.visible.main {
+            self.build_main();
+        } else if self.visible.paused {
+            self.build_pause();
+        }
+    }
+
+    fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
+        if let Some(state) = world.component::<MenuState>(self.state) {
+            self.visible = *state;
+        }
+    }
+}
+
+fn label(text: String) -> Text {
+    Text {
+        text,
+        align: Align::left(),
+        color: Vec4::WHITE,
+        font_size: FontSize::Em(0.9),
+        ..Default::default()
+    }
+}
+
+impl MenuScreen {
+    pub fn new(
+        state: Entity,
+        choice: MenuChoice,
+        tracks: Vec<String>,
+        presets: CarPresets,
+    ) -> MenuScreen {
+        MenuScreen {
+            state,
+            visible: MenuState {
+                main: false,
+                paused: false,
+            },
+            choice,
+            tracks,
+            presets,
+        }
+    }
+
+    // a line of the main menu, clicking the value moves on to the next one
+    #[allow(unused_must_use)]
+    fn option(&self, name: &str, value: String, next: fn(&mut MenuChoice, &MenuScreen)) {
+        -label(name.to_owned());
+        -Button {
+            background: ButtonBckg::Fill(Vec4::grey(0.3)),
+            callback: self.make_callback2(move |data, _btn: &Button| {
+                let mut choice = data.choice.clone();
+                next(&mut choice, data);
+                data.choice = choice;
+            }),
+            ..Default::default()
+        } << {
+            -label(value);
+        };
+    }
+
+    #[allow(unused_must_use)]
+    fn command(&self, text: &str, command: MenuCommand) {
+        -Button {
+            background: ButtonBckg::Fill(Vec4::grey(0.4)),
+            callback: self.make_callback3(move |_data, _btn: &Button, world| {
+                world.send_by_type::<Menus, _>(command.clone());
+            }),
+            ..Default::default()
+        } << {
+            -label(text.to_owned());
+        };
+    }
+
+    #[allow(unused_must_use)]
+    fn build_main(&self) {
+        let c = &self.choice;
+        let on_off = |on: bool| if on { "On" } else { "Off" }.to_owned();
+
+        -Overlay::from(Vec4::new(0.0, 0.0, 0.0, 0.6)) << {
+            -GridLayout {
+                row_heights: vec![GuiDimension::Units(40.0); 10],
+                col_widths: vec![GuiDimension::Units(160.0), GuiDimension::Units(240.0)],
+                ..Default::default()
+            } << {
+                -label("Skyrace".to_owned());
+                -SkipCell {};
+                self.option("Mode", MODES[c.mode].0.to_owned(), |c, _| {
+                    c.mode = (c.mode + 1) % MODES.len()
+                });
+                self.option(
+                    "Track",
+                    self.tracks
+                        .get(c.track)
+                        .map(|t| Track::name(t))
+                        .unwrap_or_default(),
+                    |c, menu| c.track = (c.track + 1) % menu.tracks.len().max(1),
+                );
+                self.option("Car", self.presets.get(c.car).name.clone(), |c, menu| {
+                    c.car = (c.car + 1) % menu.presets.cars.len().max(1)
+                });
+                self.option("Paint", CAR_COLORS[c.paint].0.to_owned(), |c, _| {
+                    c.paint = (c.paint + 1) % CAR_COLORS.len()
+                });
+                self.option("Opponents", format!("{}", c.opponents), |c, _| {
+                    c.opponents = (c.opponents + 1) % (MAX_OPPONENTS + 1)
+                });
+                self.option("Laps", format!("{}", c.laps), |c, _| {
+                    c.laps = c.laps % MAX_LAPS + 1
+                });
+                self.option("Damage", on_off(c.damage), |c, _| c.damage = !c.damage);
+                self.option("Sound", on_off(c.sound), |c, _| c.sound = !c.sound);
+                let start = match c.session(&self.tracks, &self.presets) {
+                    Some(session) => MenuCommand::Launch(session),
+                    None => MenuCommand::Resume,
+                };
+                self.command("Start", start);
+                self.command("Quit", MenuCommand::Quit);
+            };
+        };
+    }
+
+    #[allow(unused_must_use)]
+    fn build_pause(&self) {
+        -Overlay::from(Vec4::new(0.0, 0.0, 0.0, 0.6)) << {
+            -GridLayout {
+                row_heights: vec![GuiDimension::Units(40.0); 4],
+                col_widths: vec![GuiDimension::Units(200.0)],
+                ..Default::default()
+            } << {
+                -label("Paused".to_owned());
+                self.command("Resume", MenuCommand::Resume);
+                self.command("Restart", MenuCommand::Restart);
+                self.command("Quit", MenuCommand::Quit);
+            };
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracks() -> Vec<String> {
+        vec!["race_track.json".to_owned(), "dunes.json".to_owned()]
+    }
+
+    #[test]
+    fn the_demo_starts_no_session() {
+        let choice = MenuChoice {
+            mode: 0,
+            ..MenuChoice::new(&RaceRules::default(), &CarPresets::default())
+        };
+        assert!(choice.session(&tracks(), &CarPresets::default()).is_none());
+    }
+
+    #[test]
+    fn the_chosen_session_is_started() {
+        let presets = CarPresets::default();
+        let choice = MenuChoice {
+            mode: 2,
+            track: 1,
+            car: 2,
+            paint: 3,
+            opponents: 5,
+            sound: false,
+            laps: 4,
+            damage: false,
+        };
+        let session = choice.session(&tracks(), &presets).unwrap();
+        assert_eq!(session.mode, GameMode::TimeTrial);
+        assert!(!session.championship);
+        assert_eq!(session.track, "dunes.json");
+        assert_eq!(session.assists, presets.get(2).assists);
+        assert_eq!((session.paint, session.opponents, session.laps), (3, 5, 4));
+        assert!(!session.damage && !session.sound);
+
+        let defaults = MenuChoice::new(&RaceRules::default(), &presets);
+        let session = defaults.session(&[], &presets).unwrap();
+        assert_eq!(session.mode, GameMode::Race);
+        assert_eq!(session.track, TRACK_FILE);
+        assert_eq!(session.assists, presets.get(presets.player).assists);
+        assert!(session.damage);
+
+        let championship = MenuChoice {
+            mode: 3,
+            ..defaults
+        };
+        assert!(championship.session(&[], &presets).unwrap().championship);
+    }
+}
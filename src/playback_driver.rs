@@ -1,4 +1,6 @@
 use crate::car::CarComponent;
+use crate::menu;
+use crate::race_control::RaceStatus;
 
 extern crate serde;
 extern crate serde_json;
@@ -12,6 +14,8 @@ pub struct CarDriveState {
     pub brake: f32,
     pub throttle: f32,
     pub steer: f32,
+    #[serde(default)]
+    pub nitro: bool,
     pub update_id: usize,
 }
 
@@ -20,6 +24,7 @@ impl PartialEq for CarDriveState {
         self.brake.eq(&other.brake)
             && self.throttle.eq(&other.throttle)
             && self.steer.eq(&other.steer)
+            && self.nitro == other.nitro
     }
 }
 
@@ -31,13 +36,29 @@ pub struct PlaybackDriver {
 
 impl System for PlaybackDriver {
     fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
+        // the recording skips the paused frames too
+        if menu::is_paused(world) {
+            return;
+        }
+        let (throttle_limit, green) = world
+            .component::<RaceStatus>(self.car)
+            .map(|s| (s.throttle_limit(), s.since_green.is_some()))
+            .unwrap_or((1.0, true));
         let mut car = world.component_mut::<CarComponent>(self.car).unwrap();
 
+        // the recording starts when the lights go out
+        if !green {
+            car.throttle = 0.0;
+            car.nitro = false;
+            return;
+        }
+
         if let Some(state) = self.state_stack.last().cloned() {
             if state.update_id == self.update_id {
                 car.steer = state.steer;
                 car.brake = state.brake;
-                car.throttle = state.throttle;
+                car.throttle = state.throttle.min(throttle_limit);
+                car.nitro = state.nitro && throttle_limit >= 1.0;
                 self.state_stack.pop();
                 self.update_id = 0;
             } else {
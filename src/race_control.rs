@@ -0,0 +1,859 @@
+use crate::ai_driver::AiDriver;
+use crate::camera_on_car::CamFollowCar;
+use crate::car::{Assists, CarComponent, CarSystem, RestoreCar, TireState};
+use crate::car_driver::Autopilot;
+use crate::championship::{
+    Championship, ChampionshipConfig, ChampionshipState, RaceFinished, SetSeason,
+};
+use crate::drift::DriftSystem;
+use crate::game_mode::{GameMode, TimeAttackRules};
+use crate::menu;
+use crate::results::{SessionInfo, SessionResult};
+use crate::sounds::Sounds;
+use crate::track::{LoadTrack, PitLane, Track, TrackPath};
+
+extern crate serde;
+
+use glui::mecs::*;
+use glui::tools::serde_tools::SerdeJsonQuick;
+use glui::tools::Vec2;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const GRID_TIME: f32 = 2.0; // time given to settle on the grid (s)
+const LIGHT_INTERVAL: f32 = 1.0; // time between two start lights coming on (s)
+const START_LIGHTS: usize = 5;
+const GREEN_TIME: f32 = 2.0; // time the green signal is shown (s)
+const COUNTDOWN_CREEP: f32 = 0.1; // throttle passed through while the lights are on
+const COOL_DOWN_THROTTLE: f32 = 0.3; // throttle allowed after finishing
+const JUMP_START_THROTTLE: f32 = 0.05; // throttle before green that is a jump start
+const JUMP_START_SPEED: f32 = 0.3; // speed before green that is a jump start (m/s)
+const PIT_SPEED_TOLERANCE: f32 = 2.0; // speed above the pit limit before it is an offence (m/s)
+const PIT_STOP_SPEED: f32 = 1.0; // speed below which a car in the box is stopped (m/s)
+const BOX_LENGTH: f32 = 12.0; // m
+const RESPAWN_GHOST: f32 = 3.0; // time a respawned car passes through the others (s)
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RaceRules {
+    pub laps: usize,                    // laps to complete the race
+    pub off_track_threshold: f32,       // time allowed with all wheels off the track (s)
+    pub shortcut_threshold: f32,        // progress gained over distance driven off track (m)
+    pub wrong_way_threshold: f32,       // time allowed driving the wrong way (s)
+    pub warnings_before_penalty: usize, // warnings given before time penalties
+    pub off_track_penalty: f32,         // s
+    pub shortcut_penalty: f32,          // s
+    pub wrong_way_penalty: f32,         // s
+    pub jump_start_penalty: f32,        // s
+    pub cool_down_timeout: f32,         // time after the winner finishes to end the race (s)
+    pub pit_speeding_penalty: f32,      // s
+    pub refuel_rate: f32,               // fuel put in per second of a stop (kg/s)
+    pub tire_change_time: f32,          // time to fit fresh tires (s)
+    pub damage: bool,                   // impacts damage the cars
+    pub repair_time: f32,               // time to repair a wrecked car (s)
+    pub mode: GameMode,
+    pub time_attack: TimeAttackRules,
+}
+
+impl Default for RaceRules {
+    fn default() -> Self {
+        RaceRules {
+            laps: 3,
+            off_track_threshold: 2.0,
+            shortcut_threshold: 20.0,
+            wrong_way_threshold: 3.0,
+            warnings_before_penalty: 2,
+            off_track_penalty: 5.0,
+            shortcut_penalty: 10.0,
+            wrong_way_penalty: 10.0,
+            jump_start_penalty: 10.0,
+            cool_down_timeout: 60.0,
+            pit_speeding_penalty: 5.0,
+            refuel_rate: 6.0,
+            tire_change_time: 4.0,
+            damage: true,
+            repair_time: 20.0,
+            mode: GameMode::Race,
+            time_attack: TimeAttackRules::default(),
+        }
+    }
+}
+
+impl RaceRules {
+    pub fn load() -> RaceRules {
+        match RaceRules::load_json("race_rules.json") {
+            Ok(rules) => rules,
+            Err(e) => {
+                println!("Failed to load race rules, using defaults: {:?}", e);
+                RaceRules::default()
+            }
+        }
+    }
+}
+
+/// Puts the car back on the centreline where it was last on the track, facing the direction
+/// of travel.
+#[derive(Debug, Copy, Clone, Message)]
+pub struct Respawn(pub Entity);
+
+/// Starts the race over on its track, with the same cars on the same grid.
+#[derive(Debug, Copy, Clone, Message)]
+pub struct RestartRace;
+
+/// A session picked in the main menu, race control sets it up on the cars of the game and
+/// starts it on its track.
+#[derive(Debug, Clone, Message)]
+pub struct NewSession {
+    pub mode: GameMode,
+    pub championship: bool, // the race is the next round of the championship
+    pub track: String,
+    pub laps: usize,
+    pub damage: bool,
+    pub opponents: usize,
+    pub assists: Assists, // of the cars of the players
+    pub paint: usize,     // of the first player, the second one takes the next paint
+    pub sound: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RacePhase {
+    Grid,
+    Countdown(usize), // number of lights on
+    Green,
+    Running,
+    Chequered,
+    CoolDown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OffenceKind {
+    OffTrack,
+    Shortcut,
+    WrongWay,
+    JumpStart,
+    PitSpeeding,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Sanction {
+    Warning,
+    TimePenalty(f32),
+    LapInvalidated,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Offence {
+    pub kind: OffenceKind,
+    pub lap: usize,
+    pub time: f32,
+    pub sanction: Sanction,
+}
+
+impl Offence {
+    pub fn describe(&self) -> String {
+        let what = match self.kind {
+            OffenceKind::OffTrack => "Track limits",
+            OffenceKind::Shortcut => "Cutting the track",
+            OffenceKind::WrongWay => "Wrong way",
+            OffenceKind::JumpStart => "Jump start",
+            OffenceKind::PitSpeeding => "Pit lane speeding",
+        };
+        match self.sanction {
+            Sanction::Warning => format!("{}: warning", what),
+            Sanction::TimePenalty(t) => format!("{}: +{} s", what, t),
+            Sanction::LapInvalidated => format!("{}: lap invalidated", what),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LapTime {
+    pub time: f32,
+    pub valid: bool,
+    #[serde(default)]
+    pub tires: [TireState; 2], // front and rear at the end of the lap
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PitStop {
+    pub lap: usize,
+    pub time: f32,     // race time when the car stopped in the box (s)
+    pub duration: f32, // s
+}
+
+#[derive(Debug, Clone, Component)]
+pub struct RaceStatus {
+    pub phase: RacePhase,
+    pub since_green: Option<f32>, // race time (s)
+    pub progress: f32,            // distance driven along the centreline since the start line (m)
+    pub laps: Vec<LapTime>,
+    pub lap_start: f32,
+    pub lap_valid: bool,
+    pub offences: Vec<Offence>,
+    pub warnings: usize,
+    pub penalty_time: f32,
+    pub finish_time: Option<f32>,
+    pub race_laps: usize,
+    pub mode: GameMode,
+    pub eliminated: Option<usize>, // laps completed when the car was taken out of the race
+    pub time_left: Option<f32>,    // countdown of the car racing against the clock (s)
+    pub checkpoints: usize,        // checkpoints passed since the start
+    pub in_pit_lane: bool,
+    pub pit_limiter: bool, // faster than the pit lane limit, the throttle is cut
+    pub pit_stop: Option<f32>, // service time left while stopped in the box (s)
+    pub pit_stops: Vec<PitStop>,
+
+    pit_served: bool,
+    pit_speeding_judged: bool,
+    off_track_time: f32,
+    off_track_judged: bool,
+    cut_gain: f32,
+    wrong_way_time: f32,
+    wrong_way_judged: bool,
+    last_pos: Vec2,
+    last_valid_progress: f32, // progress when the car was last on the track (m)
+}
+
+impl RaceStatus {
+    pub fn new(progress: f32, pos: Vec2, rules: &RaceRules, cars: usize) -> RaceStatus {
+        RaceStatus {
+            phase: RacePhase::Grid,
+            since_green: None,
+            progress,
+            laps: vec![],
+            lap_start: 0.0,
+            lap_valid: true,
+            offences: vec![],
+            warnings: 0,
+            penalty_time: 0.0,
+            finish_time: None,
+            race_laps: rules.mode.race_laps(rules.laps, cars),
+            mode: rules.mode,
+            eliminated: None,
+            time_left: rules.mode.time_limit(rules),
+            checkpoints: 0,
+            in_pit_lane: false,
+            pit_limiter: false,
+            pit_stop: None,
+            pit_stops: vec![],
+            pit_served: false,
+            pit_speeding_judged: false,
+            off_track_time: 0.0,
+            off_track_judged: false,
+            cut_gain: 0.0,
+            wrong_way_time: 0.0,
+            wrong_way_judged: false,
+            last_pos: pos,
+            last_valid_progress: progress,
+        }
+    }
+
+    /// Largest throttle the driver of this car may apply in the current phase.
+    pub fn throttle_limit(&self) -> f32 {
+        match self.phase {
+            RacePhase::Grid => 0.0,
+            RacePhase::Countdown(_) => COUNTDOWN_CREEP,
+            RacePhase::CoolDown => COOL_DOWN_THROTTLE,
+            _ if self.pit_stop.is_some() || self.pit_limiter => 0.0,
+            _ if self.finish_time.is_some() || self.eliminated.is_some() => COOL_DOWN_THROTTLE,
+            _ => 1.0,
+        }
+    }
+
+    pub fn total_time(&self) -> Option<f32> {
+        self.finish_time.map(|t| t + self.penalty_time)
+    }
+
+    /// Whether this car is ahead of the other in the running order, cars lose places while
+    /// they are in the pits and cars out of the race are behind those still in it.
+    pub fn is_ahead_of(&self, other: &RaceStatus) -> bool {
+        match (self.finish_time, other.finish_time) {
+            (Some(a), Some(b)) => a < b,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => match (self.eliminated, other.eliminated) {
+                (None, Some(_)) => true,
+                (Some(_), None) => false,
+                _ => self.progress > other.progress,
+            },
+        }
+    }
+
+    pub fn completed_laps(&self) -> usize {
+        self.laps.len()
+    }
+
+    pub fn best_lap(&self) -> Option<f32> {
+        self.laps
+            .iter()
+            .filter(|l| l.valid)
+            .map(|l| l.time)
+            .fold(None, |best, t| Some(best.map_or(t, |b: f32| b.min(t))))
+    }
+
+    fn judge(&mut self, kind: OffenceKind, time: f32, rules: &RaceRules) {
+        let sanction = if rules.mode.invalidates_laps() {
+            self.lap_valid = false;
+            Sanction::LapInvalidated
+        } else if kind != OffenceKind::Shortcut
+            && kind != OffenceKind::JumpStart
+            && kind != OffenceKind::PitSpeeding
+            && self.warnings < rules.warnings_before_penalty
+        {
+            self.warnings += 1;
+            Sanction::Warning
+        } else {
+            let t = match kind {
+                OffenceKind::OffTrack => rules.off_track_penalty,
+                OffenceKind::Shortcut => rules.shortcut_penalty,
+                OffenceKind::WrongWay => rules.wrong_way_penalty,
+                OffenceKind::JumpStart => rules.jump_start_penalty,
+                OffenceKind::PitSpeeding => rules.pit_speeding_penalty,
+            };
+            self.penalty_time += t;
+            Sanction::TimePenalty(t)
+        };
+
+        self.offences.push(Offence {
+            kind,
+            lap: self.laps.len(),
+            time,
+            sanction,
+        });
+    }
+}
+
+/// Loads the track for every system racing on it, race control last as it restarts the race on
+/// the new layout. Loading the same track again restarts the race.
+pub fn load_track(world: &mut StaticWorld, file: &str) {
+    world.send_by_type::<Track, _>(LoadTrack(file.to_owned()));
+    world.send_by_type::<AiDriver, _>(LoadTrack(file.to_owned()));
+    world.send_by_type::<CamFollowCar, _>(LoadTrack(file.to_owned()));
+    world.send_by_type::<DriftSystem, _>(LoadTrack(file.to_owned()));
+    world.send_by_type::<RaceControl, _>(LoadTrack(file.to_owned()));
+}
+
+pub fn format_time(t: f32) -> String {
+    let minutes = (t / 60.0).floor();
+    format!("{}:{:06.3}", minutes, t - minutes * 60.0)
+}
+
+pub struct RaceControl {
+    cars: Vec<Entity>,
+    session: SessionInfo,
+    path: TrackPath,
+    pit_lane: Option<PitLane>,
+    rules: RaceRules,
+    time: f32,
+    phase: RacePhase,
+    phase_start: f32,
+    lights_out_delay: f32,
+    rng: XorShiftRng,
+    green_at: Option<f32>,
+    results_saved: bool,
+    retired: Vec<(usize, RaceStatus, CarComponent)>, // cars taken off the track, by grid index
+    benched: Vec<(usize, CarComponent)>,             // cars sitting the session out, by grid index
+}
+
+impl System for RaceControl {
+    fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
+        if let Some(LoadTrack(file)) = msg.downcast_ref::<LoadTrack>() {
+            match Track::centreline(file) {
+                Ok(path) => {
+                    self.path = path;
+                    self.pit_lane = Track::pit_lane(file).unwrap_or(None);
+                    self.session.track = file.clone();
+                    self.restart(world);
+                }
+                Err(e) => println!("Failed to read path of {}: {:?}", file, e),
+            }
+        }
+        if let Some(Respawn(e)) = msg.downcast_ref::<Respawn>() {
+            self.respawn(*e, world);
+        }
+        if msg.downcast_ref::<RestartRace>().is_some() {
+            load_track(world, &self.session.track.clone());
+        }
+        if let Some(session) = msg.downcast_ref::<NewSession>() {
+            self.start_session(session.clone(), world);
+        }
+    }
+
+    fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) {
+            return;
+        }
+        let dt = delta_time.as_secs_f32();
+        self.time += dt;
+
+        let half_width = Track::track_width() / 2.0;
+        let length = self.path.length();
+        let since_green = self.green_at.map(|g| self.time - g);
+        let clock = since_green.unwrap_or(0.0);
+        let mut finished = 0;
+        let mut out = self.retired.len();
+        let mut serviced = vec![];
+        let mut timed_out = vec![];
+
+        for e in self.cars.iter().copied() {
+            let car = match world.component::<CarComponent>(e) {
+                Some(car) => *car,
+                None => continue,
+            };
+            let status = world.component_mut::<RaceStatus>(e).unwrap();
+
+            status.phase = self.phase;
+            status.since_green = since_green;
+
+            match self.phase {
+                RacePhase::Grid | RacePhase::Countdown(_) => {
+                    // judged by the throttle and the speed, the position shifts a little while
+                    // the car settles on the grid
+                    let moving =
+                        car.throttle > JUMP_START_THROTTLE || car.speed() > JUMP_START_SPEED;
+                    if moving
+                        && !status
+                            .offences
+                            .iter()
+                            .any(|o| o.kind == OffenceKind::JumpStart)
+                    {
+                        status.judge(OffenceKind::JumpStart, clock, &self.rules);
+                    }
+                    status.last_pos = car.position;
+                    continue;
+                }
+                RacePhase::CoolDown => continue,
+                _ => {}
+            }
+
+            if status.finish_time.is_some() {
+                finished += 1;
+                continue;
+            }
+            if status.eliminated.is_some() {
+                out += 1;
+                continue;
+            }
+
+            let proj = self.path.project(car.position);
+
+            let last_progress = status.progress.rem_euclid(length);
+            let mut delta = proj.progress - last_progress;
+            if delta > length / 2.0 {
+                delta -= length;
+            }
+            if delta < -length / 2.0 {
+                delta += length;
+            }
+            status.progress += delta;
+
+            let travelled = (car.position - status.last_pos).length();
+            status.last_pos = car.position;
+
+            // lap completion
+            let lap = status.laps.len();
+            if status.progress >= (lap + 1) as f32 * length {
+                status.laps.push(LapTime {
+                    time: clock - status.lap_start,
+                    valid: status.lap_valid,
+                    tires: car.tires,
+                });
+                status.lap_start = clock;
+                status.lap_valid = true;
+
+                if status.laps.len() >= status.race_laps {
+                    status.finish_time = Some(clock);
+                    finished += 1;
+                    continue;
+                }
+            }
+
+            // pit lane, speed limit and service in the box
+            let in_pit_lane = self
+                .pit_lane
+                .map(|pit| pit.is_in(&proj, length))
+                .unwrap_or(false);
+            status.in_pit_lane = in_pit_lane;
+            if let Some(pit) = self.pit_lane.filter(|_| in_pit_lane) {
+                status.pit_limiter = car.speed() > pit.speed_limit;
+                if car.speed() > pit.speed_limit + PIT_SPEED_TOLERANCE
+                    && !status.pit_speeding_judged
+                {
+                    status.pit_speeding_judged = true;
+                    status.judge(OffenceKind::PitSpeeding, clock, &self.rules);
+                }
+
+                match status.pit_stop {
+                    None if !status.pit_served
+                        && car.speed() < PIT_STOP_SPEED
+                        && pit.to_box(proj.progress, length).abs() < BOX_LENGTH / 2.0 =>
+                    {
+                        let refuel = (car.config.fuel.capacity - car.fuel) / self.rules.refuel_rate;
+                        let duration = self.rules.tire_change_time.max(refuel)
+                            + self.rules.repair_time * car.damage.total();
+                        status.pit_stop = Some(duration);
+                        status.pit_stops.push(PitStop {
+                            lap: status.laps.len(),
+                            time: clock,
+                            duration,
+                        });
+                    }
+                    Some(left) if left <= dt => {
+                        status.pit_stop = None;
+                        status.pit_served = true;
+                        serviced.push(e);
+                    }
+                    Some(left) => status.pit_stop = Some(left - dt),
+                    None => {}
+                }
+            } else {
+                status.pit_limiter = false;
+                status.pit_speeding_judged = false;
+                status.pit_served = false;
+            }
+
+            // track limits, all four wheels off
+            let f = car.dir();
+            let r = car.right();
+            let cfg = &car.config;
+            let all_off = [
+                car.position + f * cfg.cg_to_front_axle + r * cfg.width / 2.0,
+                car.position + f * cfg.cg_to_front_axle - r * cfg.width / 2.0,
+                car.position - f * cfg.cg_to_rear_axle + r * cfg.width / 2.0,
+                car.position - f * cfg.cg_to_rear_axle - r * cfg.width / 2.0,
+            ]
+            .iter()
+            .all(|p| self.path.project(*p).offset.abs() > half_width);
+
+            if all_off && !in_pit_lane {
+                status.off_track_time += dt;
+                if status.off_track_time > self.rules.off_track_threshold
+                    && !status.off_track_judged
+                {
+                    status.off_track_judged = true;
+                    status.judge(OffenceKind::OffTrack, clock, &self.rules);
+                }
+            } else {
+                status.off_track_time = 0.0;
+                status.off_track_judged = false;
+            }
+            if proj.offset.abs() <= half_width || in_pit_lane {
+                status.last_valid_progress = status.progress;
+            }
+
+            // shortcuts, progress gained faster than the car actually moved
+            if proj.offset.abs() > half_width && !in_pit_lane {
+                status.cut_gain += delta - travelled;
+            } else {
+                if status.cut_gain > self.rules.shortcut_threshold {
+                    status.judge(OffenceKind::Shortcut, clock, &self.rules);
+                }
+                status.cut_gain = 0.0;
+            }
+
+            // wrong way
+            if car.velocity.dot(proj.dir) < -1.0 {
+                status.wrong_way_time += dt;
+                if status.wrong_way_time > self.rules.wrong_way_threshold
+                    && !status.wrong_way_judged
+                {
+                    status.wrong_way_judged = true;
+                    status.judge(OffenceKind::WrongWay, clock, &self.rules);
+                }
+            } else {
+                status.wrong_way_time = 0.0;
+                status.wrong_way_judged = false;
+            }
+
+            if self.rules.mode.judge(status, &self.rules, length, dt) {
+                timed_out.push(e);
+            }
+        }
+
+        for e in serviced {
+            world.component_mut::<CarComponent>(e).unwrap().service();
+        }
+        for e in timed_out {
+            self.retire(e, world);
+            out += 1;
+        }
+        if self.phase != RacePhase::CoolDown {
+            let standings = self
+                .cars
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| world.component::<RaceStatus>(*e).map(|s| (i, s)))
+                .collect::<Vec<_>>();
+            if let Some(i) = self.rules.mode.eliminate(&standings, out) {
+                self.retire(self.cars[i], world);
+                out += 1;
+            }
+        }
+
+        self.advance_phase(finished, out);
+
+        // the demo behind the main menu leaves no results
+        let demo = self
+            .cars
+            .iter()
+            .any(|e| world.component::<Autopilot>(*e).is_some());
+        if self.phase == RacePhase::CoolDown && !self.results_saved && !demo {
+            self.results_saved = true;
+            let mut entries = self
+                .cars
+                .iter()
+                .enumerate()
+                .filter_map(|(i, e)| {
+                    let status = world.component::<RaceStatus>(*e)?.clone();
+                    let config = world.component::<CarComponent>(*e)?.config;
+                    Some((i, status, config))
+                })
+                .collect::<Vec<_>>();
+            entries.extend(
+                self.retired
+                    .iter()
+                    .map(|(i, status, car)| (*i, status.clone(), car.config)),
+            );
+            let results = SessionResult::collect(&entries, &self.session, &self.rules);
+            match results.save() {
+                Ok(path) => println!("Results saved to {}", path),
+                Err(e) => println!("Failed to save results: {:?}", e),
+            }
+            world.send_by_type::<Championship, _>(RaceFinished(results));
+        }
+    }
+}
+
+impl RaceControl {
+    pub fn new(
+        cars: Vec<Entity>,
+        session: SessionInfo,
+        rules: RaceRules,
+        world: &mut StaticWorld,
+    ) -> RaceControl {
+        let path = Track::centreline(&session.track).expect("Failed to read track!");
+        let pit_lane = Track::pit_lane(&session.track).unwrap_or(None);
+        let seed = session.seed;
+        let mut race_control = RaceControl {
+            cars,
+            session,
+            path,
+            pit_lane,
+            rules,
+            time: 0.0,
+            phase: RacePhase::Grid,
+            phase_start: 0.0,
+            lights_out_delay: 0.0,
+            rng: XorShiftRng::seed_from_u64(seed),
+            green_at: None,
+            results_saved: false,
+            retired: vec![],
+            benched: vec![],
+        };
+        race_control.reset_status(world);
+        race_control
+    }
+
+    /// Puts every car back to its grid slot and starts a new race.
+    fn restart(&mut self, world: &mut StaticWorld) {
+        // the cars taken off the track come back on the grid
+        for (i, _, car) in std::mem::take(&mut self.retired) {
+            world.add_component(self.cars[i], car);
+            world.send_by_type::<CarSystem, _>(RestoreCar(self.cars[i]));
+        }
+        for (i, e) in self.cars.iter().copied().enumerate() {
+            let (heading, position) = self.path.grid_slot(self.session.grid[i]);
+            let car = match world.component_mut::<CarComponent>(e) {
+                Some(car) => car,
+                None => continue,
+            };
+            car.set_down(heading, position);
+            car.steer = 0.0;
+            car.throttle = 0.0;
+            car.ghost = 0.0;
+            car.boost = car.config.boost.capacity;
+            car.service();
+        }
+        self.phase = RacePhase::Grid;
+        self.phase_start = self.time;
+        self.green_at = None;
+        self.results_saved = false;
+        self.reset_status(world);
+    }
+
+    /// Sets up the session picked in the main menu: the players take their cars back from the
+    /// autopilot, the cars beyond the opponents asked for sit it out and the systems the
+    /// session changes hear of it before the race starts on its track.
+    fn start_session(&mut self, mut session: NewSession, world: &mut StaticWorld) {
+        let mut season = None;
+        if session.championship && session.mode.in_championship() {
+            let config = ChampionshipConfig::load();
+            let state = ChampionshipState::load_or_new(self.session.seed, &config);
+            match state.track(&config) {
+                Some(track) => {
+                    session.track = track.clone();
+                    session.laps = config.laps;
+                    season = Some((config, state));
+                }
+                None => println!("The championship has no tracks, racing a single race"),
+            }
+        }
+        self.rules.mode = session.mode;
+        self.rules.laps = session.laps;
+        self.rules.damage = session.damage;
+
+        // the whole grid comes back before the field of the session is picked
+        let benched = std::mem::take(&mut self.benched);
+        let retired = std::mem::take(&mut self.retired)
+            .into_iter()
+            .map(|(i, _, car)| (i, car));
+        for (i, car) in benched.into_iter().chain(retired) {
+            world.add_component(self.cars[i], car);
+            world.send_by_type::<CarSystem, _>(RestoreCar(self.cars[i]));
+        }
+
+        let players = self.session.human_cars;
+        let field = (players + session.mode.opponents(session.opponents)).max(1);
+        for (i, e) in self.cars.iter().copied().enumerate() {
+            world.remove_component::<Autopilot>(e);
+            let mut car = match world.component::<CarComponent>(e).copied() {
+                Some(car) => car,
+                None => continue,
+            };
+            if i >= field {
+                self.benched.push((i, car));
+                world.remove_component::<RaceStatus>(e);
+                CarSystem::remove_car(world, e);
+            } else if i < players {
+                // repainted, the body is built again
+                car.config.assists = session.assists;
+                car.color = menu::car_color(session.paint + i);
+                CarSystem::remove_car(world, e);
+                world.add_component(e, car);
+                world.send_by_type::<CarSystem, _>(RestoreCar(e));
+            }
+        }
+
+        world.send_by_type::<Championship, _>(SetSeason(season));
+        world.send_by_type::<CarSystem, _>(session.clone());
+        world.send_by_type::<DriftSystem, _>(session.clone());
+        world.send_by_type::<Sounds, _>(session.clone());
+        world.send_by_type::<CamFollowCar, _>(session.clone());
+        load_track(world, &session.track);
+    }
+
+    /// Places the car on the centreline at its last valid progress, at rest and ghosting
+    /// through the other cars for a while.
+    fn respawn(&mut self, e: Entity, world: &mut StaticWorld) {
+        let progress = match world.component::<RaceStatus>(e) {
+            Some(status) if status.finish_time.is_none() => status.last_valid_progress,
+            Some(status) => status.progress,
+            None => return,
+        };
+        // before the start the car goes back to its grid slot
+        let slot = self.cars.iter().position(|c| *c == e);
+        let (heading, position) = match (self.phase, slot) {
+            (RacePhase::Grid, Some(i)) | (RacePhase::Countdown(_), Some(i)) => {
+                self.path.grid_slot(self.session.grid[i])
+            }
+            _ => {
+                let (position, dir) = self.path.point_at(progress);
+                (dir.y.atan2(dir.x), position)
+            }
+        };
+
+        let car = match world.component_mut::<CarComponent>(e) {
+            Some(car) => car,
+            None => return,
+        };
+        car.set_down(heading, position);
+        car.ghost = RESPAWN_GHOST;
+
+        let status = world.component_mut::<RaceStatus>(e).unwrap();
+        status.progress = progress;
+        status.last_pos = position;
+        status.off_track_time = 0.0;
+        status.off_track_judged = false;
+        status.cut_gain = 0.0;
+        status.wrong_way_time = 0.0;
+        status.wrong_way_judged = false;
+    }
+
+    fn reset_status(&mut self, world: &mut StaticWorld) {
+        let length = self.path.length();
+        let cars = self
+            .cars
+            .iter()
+            .filter(|e| world.component::<CarComponent>(**e).is_some())
+            .count();
+
+        for e in self.cars.iter().copied() {
+            let pos = match world.component::<CarComponent>(e) {
+                Some(car) => car.position,
+                None => continue,
+            };
+            let mut progress = self.path.project(pos).progress;
+            if progress > length / 2.0 {
+                progress -= length;
+            }
+            world.add_component(e, RaceStatus::new(progress, pos, &self.rules, cars));
+        }
+        self.lights_out_delay = self.rng.gen_range(0.2..2.0);
+    }
+
+    /// Takes the car out of the race, off the track too if the mode removes cars.
+    fn retire(&mut self, e: Entity, world: &mut StaticWorld) {
+        let slot = self.cars.iter().position(|c| *c == e);
+        let status = match world.component_mut::<RaceStatus>(e) {
+            Some(status) => status,
+            None => return,
+        };
+        status.eliminated = Some(status.laps.len());
+        status.pit_stop = None;
+        let status = status.clone();
+
+        if let (true, Some(i)) = (self.rules.mode.removes_cars(), slot) {
+            if let Some(car) = world.component::<CarComponent>(e).copied() {
+                self.retired.push((i, status, car));
+                world.remove_component::<RaceStatus>(e);
+                CarSystem::remove_car(world, e);
+            }
+        }
+    }
+
+    fn set_phase(&mut self, phase: RacePhase) {
+        self.phase = phase;
+        self.phase_start = self.time;
+    }
+
+    fn advance_phase(&mut self, finished: usize, out: usize) {
+        let t = self.time - self.phase_start;
+
+        match self.phase {
+            RacePhase::Grid if t > GRID_TIME => self.set_phase(RacePhase::Countdown(1)),
+            RacePhase::Countdown(n) if n < START_LIGHTS && t > LIGHT_INTERVAL => {
+                self.set_phase(RacePhase::Countdown(n + 1))
+            }
+            RacePhase::Countdown(_) if t > LIGHT_INTERVAL + self.lights_out_delay => {
+                self.green_at = Some(self.time);
+                self.set_phase(RacePhase::Green);
+            }
+            RacePhase::Green if t > GREEN_TIME => self.set_phase(RacePhase::Running),
+            RacePhase::Green | RacePhase::Running if finished > 0 => {
+                self.set_phase(RacePhase::Chequered)
+            }
+            // every car is out of the race before any finished
+            RacePhase::Green | RacePhase::Running if out == self.cars.len() => {
+                self.set_phase(RacePhase::CoolDown)
+            }
+            RacePhase::Chequered
+                if finished + out == self.cars.len() || t > self.rules.cool_down_timeout =>
+            {
+                self.set_phase(RacePhase::CoolDown)
+            }
+            _ => {}
+        }
+    }
+}
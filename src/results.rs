@@ -0,0 +1,257 @@
+use crate::car::CarConfig;
+use crate::game_mode::GameMode;
+use crate::race_control::{format_time, LapTime, Offence, PitStop, RaceRules, RaceStatus};
+use crate::track::Track;
+
+extern crate serde;
+
+use glui::tools::serde_tools::{SerdeError, SerdeJsonQuick};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const RESULTS_DIR: &str = "results";
+
+/// What identifies a race apart from its outcome.
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub track: String,
+    pub seed: u64,
+    pub human_cars: usize, // the first cars of the grid are driven by people
+    pub grid: Vec<usize>,  // starting slot of each car
+}
+
+impl SessionInfo {
+    pub fn driver_name(&self, index: usize) -> String {
+        if index < self.human_cars {
+            if self.human_cars == 1 {
+                "Player".to_owned()
+            } else {
+                format!("Player {}", index + 1)
+            }
+        } else {
+            format!("AI {}", index + 1 - self.human_cars)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CarResult {
+    pub position: usize,
+    pub driver: String,
+    pub human: bool,
+    pub total_time: Option<f32>, // None if the car did not finish
+    pub gap: Option<f32>,        // to the winner
+    pub best_lap: Option<f32>,
+    pub laps: Vec<LapTime>,
+    pub penalty_time: f32,
+    pub penalties: Vec<Offence>,
+    #[serde(default)]
+    pub pit_stops: Vec<PitStop>,
+    #[serde(default)]
+    pub eliminated: Option<usize>, // laps completed when taken out of the race
+    pub preset: CarConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionResult {
+    pub timestamp: u64, // seconds since the unix epoch
+    pub track: String,
+    pub seed: u64,
+    pub laps: usize,
+    #[serde(default)]
+    pub mode: GameMode,
+    pub cars: Vec<CarResult>,
+}
+
+impl SessionResult {
+    /// Results of the cars given by their grid index, final status and preset.
+    pub fn collect(
+        entries: &[(usize, RaceStatus, CarConfig)],
+        session: &SessionInfo,
+        rules: &RaceRules,
+    ) -> SessionResult {
+        let mut entries = entries.to_vec();
+
+        // finishers by time, then the cars still running and the ones taken out of the race,
+        // by distance covered
+        entries.sort_by(
+            |(_, a, _), (_, b, _)| match (a.total_time(), b.total_time()) {
+                (Some(ta), Some(tb)) => ta.partial_cmp(&tb).unwrap(),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => a
+                    .eliminated
+                    .is_some()
+                    .cmp(&b.eliminated.is_some())
+                    .then(b.progress.partial_cmp(&a.progress).unwrap()),
+            },
+        );
+        let laps = entries
+            .iter()
+            .map(|(_, s, _)| s.race_laps)
+            .max()
+            .unwrap_or(rules.laps);
+
+        let winner_time = entries.first().and_then(|(_, s, _)| s.total_time());
+
+        SessionResult {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            track: Track::name(&session.track),
+            seed: session.seed,
+            laps,
+            mode: rules.mode,
+            cars: entries
+                .into_iter()
+                .enumerate()
+                .map(|(position, (i, status, preset))| CarResult {
+                    position: position + 1,
+                    driver: session.driver_name(i),
+                    human: i < session.human_cars,
+                    total_time: status.total_time(),
+                    gap: status.total_time().and_then(|t| winner_time.map(|w| t - w)),
+                    best_lap: status.best_lap(),
+                    laps: status.laps.clone(),
+                    penalty_time: status.penalty_time,
+                    penalties: status.offences.clone(),
+                    pit_stops: status.pit_stops.clone(),
+                    eliminated: status.eliminated,
+                    preset,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self) -> Result<String, SerdeError> {
+        if let Err(e) = fs::create_dir_all(RESULTS_DIR) {
+            println!("Failed to create {}: {:?}", RESULTS_DIR, e);
+        }
+        let path = format!("{}/{}_{}.json", RESULTS_DIR, self.timestamp, self.track);
+        self.save_json(&path)?;
+        Ok(path)
+    }
+
+    pub fn load_all() -> Vec<SessionResult> {
+        let mut sessions = vec![];
+        if let Ok(dir) = fs::read_dir(RESULTS_DIR) {
+            for entry in dir.flatten() {
+                let path = entry.path();
+                if path.extension().map(|e| e == "json").unwrap_or(false) {
+                    match SessionResult::load_json(&path.to_string_lossy()) {
+                        Ok(session) => sessions.push(session),
+                        Err(e) => println!("Skipping {}: {:?}", path.display(), e),
+                    }
+                }
+            }
+        }
+        sessions.sort_by_key(|s| s.timestamp);
+        sessions
+    }
+}
+
+// yyyy-mm-dd hh:mm in UTC, from days since the epoch by the civil calendar algorithm
+fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60
+    )
+}
+
+fn format_opt_time(t: Option<f32>) -> String {
+    t.map(format_time).unwrap_or_else(|| "-".to_owned())
+}
+
+/// Entry point of `skyrace results`, prints past sessions and personal bests.
+pub fn print_history() {
+    let sessions = SessionResult::load_all();
+    if sessions.is_empty() {
+        println!("No results in {}/ yet.", RESULTS_DIR);
+        return;
+    }
+
+    println!(
+        "{:<17} {:<16} {:<12} {:>4} {:<10} {:>10} {:>10} {:>8}",
+        "Date", "Track", "Mode", "Laps", "Winner", "Time", "Best lap", "You"
+    );
+    for s in sessions.iter() {
+        let winner = s.cars.first();
+        let player = s
+            .cars
+            .iter()
+            .find(|c| c.human)
+            .map(|c| format!("P{}", c.position))
+            .unwrap_or_else(|| "-".to_owned());
+        let best_lap = s
+            .cars
+            .iter()
+            .filter_map(|c| c.best_lap)
+            .fold(None, |best: Option<f32>, t| {
+                Some(best.map_or(t, |b| b.min(t)))
+            });
+
+        println!(
+            "{:<17} {:<16} {:<12} {:>4} {:<10} {:>10} {:>10} {:>8}",
+            format_timestamp(s.timestamp),
+            s.track,
+            s.mode.name(),
+            s.laps,
+            winner.map(|c| c.driver.as_str()).unwrap_or("-"),
+            format_opt_time(winner.and_then(|c| c.total_time)),
+            format_opt_time(best_lap),
+            player
+        );
+    }
+
+    // personal bests of human drivers per track and race distance
+    let mut bests: HashMap<String, (Option<f32>, Option<f32>)> = HashMap::new();
+    for s in sessions.iter() {
+        for c in s.cars.iter().filter(|c| c.human) {
+            let key = format!("{}, {} laps", s.track, s.laps);
+            let entry = bests.entry(key).or_insert((None, None));
+            if let Some(t) = c.best_lap {
+                entry.0 = Some(entry.0.map_or(t, |b| b.min(t)));
+            }
+            if let Some(t) = c.total_time {
+                if s.mode == GameMode::Race {
+                    entry.1 = Some(entry.1.map_or(t, |b| b.min(t)));
+                }
+            }
+        }
+    }
+
+    if !bests.is_empty() {
+        println!();
+        println!("{:<24} {:>10} {:>10}", "Personal bests", "Lap", "Race");
+        let mut tracks = bests.keys().cloned().collect::<Vec<String>>();
+        tracks.sort();
+        for track in tracks {
+            let (lap, race) = bests[&track];
+            println!(
+                "{:<24} {:>10} {:>10}",
+                track,
+                format_opt_time(lap),
+                format_opt_time(race)
+            );
+        }
+    }
+}
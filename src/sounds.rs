@@ -1,4 +1,7 @@
 use crate::car::CarComponent;
+use crate::input::{Action, Controls};
+use crate::menu;
+use crate::race_control::NewSession;
 use glui::mecs::*;
 use glui::tools::{Camera, Vec3};
 use rodio::{Sample, Sink, Source, SpatialSink};
@@ -24,13 +27,21 @@ pub struct Sounds {
     sender: Sender<SoundMsg>,
     cars: Vec<Entity>,
     camera: Entity,
+    listeners: Vec<Entity>, // cars of the local players, heard from instead of the camera
     music: bool,
+    controls: Controls,
 }
 
 impl System for Sounds {
     fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
-        let car = world.component::<CarComponent>(self.cars[0]).unwrap();
-        let s = car.speed();
+        // the engines fall silent while the game is paused
+        let paused = menu::is_paused(world);
+        let s = self
+            .cars
+            .first()
+            .and_then(|car| world.component::<CarComponent>(*car))
+            .map(|car| car.speed())
+            .unwrap_or(0.0);
         self.sender
             .send(SoundMsg::MusicSpeed((s * 0.01) * (s * 0.01) * 0.05 + 1.0))
             .unwrap_or_default();
@@ -42,37 +53,71 @@ impl System for Sounds {
             .params
             .spatial;
 
+        let mut ears = vec![];
+        for e in self.listeners.iter() {
+            let car = match world.component::<CarComponent>(*e) {
+                Some(car) => car,
+                None => continue,
+            };
+            let p = car.pos3() + Vec3::new(0.0, 1.0, 0.0);
+            ears.push((p - car.right3() * 2.0, p + car.right3() * 2.0));
+        }
+        if ears.is_empty() {
+            ears.push((cam.pos - cam.r() * 2.0, cam.pos + cam.r() * 2.0));
+        }
+
         let mut specs = vec![];
 
         for car in self.cars.iter() {
-            let car = world.component::<CarComponent>(*car).unwrap();
+            // a car taken off the track keeps its engine, silent
+            let car = match world.component::<CarComponent>(*car) {
+                Some(car) => car,
+                None => {
+                    let (left, right) = ears[0];
+                    specs.push((1.0, 0.0, cam.pos, left, right));
+                    continue;
+                }
+            };
             let throttle = car.throttle;
-            let speed = throttle * 1.5 + 0.5;
-            let volume = throttle * 2.0 + 4.0;
-
-            specs.push((
-                speed,
-                volume,
-                car.pos3(),
-                cam.pos - cam.r() * 2.0,
-                cam.pos + cam.r() * 2.0,
-            ));
+            let (pitch, loudness) = if car.boosting { (0.8, 2.0) } else { (0.0, 0.0) };
+            let speed = throttle * 1.5 + 0.5 + pitch;
+            let volume = if paused {
+                0.0
+            } else {
+                throttle * 2.0 + 4.0 + loudness
+            };
+            let p = car.pos3();
+
+            // every engine is heard by the listener closest to it
+            let (left, right) = ears
+                .iter()
+                .copied()
+                .min_by(|a, b| {
+                    let da = ((a.0 + a.1) * 0.5 - p).length();
+                    let db = ((b.0 + b.1) * 0.5 - p).length();
+                    da.partial_cmp(&db).unwrap()
+                })
+                .unwrap();
+
+            specs.push((speed, volume, p, left, right));
         }
         self.sender
             .send(SoundMsg::Engine(specs))
             .unwrap_or_default();
     }
 
+    fn receive(&mut self, msg: &Box<dyn Message>, _world: &mut StaticWorld) {
+        if let Some(session) = msg.downcast_ref::<NewSession>() {
+            if session.sound != self.music {
+                self.toggle_music();
+            }
+        }
+    }
+
     fn window_event(&mut self, event: &GlutinWindowEvent, _world: &mut StaticWorld) -> bool {
-        if let GlutinWindowEvent::KeyboardInput { input, .. } = event {
-            let press = input.state == GlutinElementState::Pressed;
-            if let Some(key) = input.virtual_keycode {
-                if key == GlutinKey::M && !press {
-                    self.music = !self.music;
-                    self.sender
-                        .send(SoundMsg::PlayMusic(self.music))
-                        .unwrap_or_default();
-                }
+        if let Some((action, press)) = self.controls.action(event, None) {
+            if action == Action::ToggleMusic && !press {
+                self.toggle_music();
             }
         }
 
@@ -81,7 +126,14 @@ impl System for Sounds {
 }
 
 impl Sounds {
-    pub fn new(cars: Vec<Entity>, camera: Entity, muted: bool) -> Sounds {
+    fn toggle_music(&mut self) {
+        self.music = !self.music;
+        self.sender
+            .send(SoundMsg::PlayMusic(self.music))
+            .unwrap_or_default();
+    }
+
+    pub fn new(cars: Vec<Entity>, camera: Entity, muted: bool, controls: Controls) -> Sounds {
         let (tx, rx) = mpsc::channel();
         let n = cars.len();
 
@@ -186,9 +238,17 @@ impl Sounds {
             sender: tx,
             cars,
             camera,
+            listeners: vec![],
             music: !muted,
+            controls,
         }
     }
+
+    /// Mixes the engines as heard from the cars of the players when they share the screen.
+    pub fn with_listeners(mut self, listeners: Vec<Entity>) -> Sounds {
+        self.listeners = listeners;
+        self
+    }
 }
 
 impl Drop for Sounds {
@@ -1,5 +1,6 @@
-use glui::mecs::{DrawComponent, System};
-use glui::mecs::{GlutinKey, StaticWorld};
+use crate::input::{Action, Controls};
+use crate::menu;
+use glui::mecs::{DrawComponent, StaticWorld, System};
 use glui::tools::{Uniform, Vec3};
 use std::f32::consts::PI;
 use std::time::Duration;
@@ -7,18 +8,25 @@ use std::time::Duration;
 #[derive(Debug)]
 pub struct SunMover {
     time: f32,
+    controls: Controls,
 }
 
 impl SunMover {
-    pub fn new() -> SunMover {
-        SunMover { time: 0.0 }
+    pub fn new(controls: Controls) -> SunMover {
+        SunMover {
+            time: 0.0,
+            controls,
+        }
     }
 }
 
 impl System for SunMover {
     fn update(&mut self, delta_time: Duration, world: &mut StaticWorld) {
+        if menu::is_paused(world) {
+            return;
+        }
         self.time += delta_time.as_secs_f32()
-            * if world.is_key_pressed(GlutinKey::O) {
+            * if self.controls.is_held(world, Action::FastSun, None) {
                 2.0
             } else {
                 0.02
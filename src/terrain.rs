@@ -2,9 +2,10 @@ use glui::graphics::{DrawShaderSelector, RenderCommand, RenderSequence};
 use glui::mecs::{DrawComponent, Entity, StaticWorld, System};
 use glui::tools::{
     parsurf, parsurf_indices, Buffer, DrawMode, DrawShader, FloatTexture, LinSpace, Mat4,
-    RgbaTexture, Uniform, Vec2, Vec3, VertexArray,
+    RgbaTexture, Smoothstep, Uniform, Vec2, Vec3, VertexArray,
 };
 
+use crate::track::Track;
 use crate::utilities::watch;
 use noise::{NoiseFn, OpenSimplex};
 use notify::DebouncedEvent::NoticeWrite;
@@ -12,6 +13,13 @@ use notify::{DebouncedEvent, RecommendedWatcher};
 use std::sync::mpsc::Receiver;
 use std::time::Duration;
 
+const TERRAIN_SIZE: f32 = 2300.0; // world size of the terrain square, as in terrain.vert (m)
+const SLOPE_SAMPLE: f32 = 1.5; // distance the slope is measured over on each side (m)
+const TERRAIN_SAMPLES: usize = 512; // heightmap samples along each side of the terrain
+const LEVEL_MARGIN: f32 = 6.0; // flat ground kept beside the road and the pit lane (m)
+const LEVEL_BLEND: f32 = 60.0; // distance over which the dunes rise from the flat ground (m)
+const LEVEL_STEP: f32 = 2.0; // spacing of the points of the tracks levelled around (m)
+
 struct HmapSampler {
     gen: OpenSimplex,
 }
@@ -39,6 +47,48 @@ impl HmapSampler {
     }
 }
 
+/// CPU side copy of the heightmap for height queries in world coordinates.
+#[derive(Debug, Clone)]
+pub struct HeightMap {
+    data: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl HeightMap {
+    /// Height of the terrain at the given point of the ground plane (x, z).
+    pub fn height_at(&self, p: Vec2) -> f32 {
+        // the shader samples the texture at x / size, z / size and the texture rows are the
+        // x samples of the generator, hence the swap of axes
+        let col = (p.x / TERRAIN_SIZE * self.height as f32 - 0.5)
+            .max(0.0)
+            .min(self.height as f32 - 1.0);
+        let row = (p.y / TERRAIN_SIZE * self.width as f32 - 0.5)
+            .max(0.0)
+            .min(self.width as f32 - 1.0);
+
+        let i = (row as usize).min(self.width - 2);
+        let j = (col as usize).min(self.height - 2);
+        let fi = row - i as f32;
+        let fj = col - j as f32;
+
+        let h = |i: usize, j: usize| self.data[i * self.height + j];
+        let top = h(i, j) * (1.0 - fj) + h(i, j + 1) * fj;
+        let bottom = h(i + 1, j) * (1.0 - fj) + h(i + 1, j + 1) * fj;
+        top * (1.0 - fi) + bottom * fi
+    }
+
+    /// Gradient of the terrain at the given point, rise per metre along x and z.
+    pub fn slope_at(&self, p: Vec2) -> Vec2 {
+        let dx = Vec2::new(SLOPE_SAMPLE, 0.0);
+        let dz = Vec2::new(0.0, SLOPE_SAMPLE);
+        Vec2::new(
+            self.height_at(p + dx) - self.height_at(p - dx),
+            self.height_at(p + dz) - self.height_at(p - dz),
+        ) * (0.5 / SLOPE_SAMPLE)
+    }
+}
+
 #[allow(dead_code)]
 pub struct Terrain {
     draw: Entity,
@@ -50,6 +100,7 @@ pub struct Terrain {
     channel: (RecommendedWatcher, Receiver<DebouncedEvent>),
     shader: DrawShader,
     hmap: HmapSampler,
+    height_map: HeightMap,
 }
 
 impl System for Terrain {
@@ -62,9 +113,12 @@ impl System for Terrain {
 }
 
 impl Terrain {
-    pub fn new(world: &mut StaticWorld) -> Terrain {
+    /// The desert with the ground levelled under the given tracks, so that any of them can be
+    /// raced on without regenerating it.
+    pub fn new(world: &mut StaticWorld, tracks: &[String]) -> Terrain {
         let hmap = HmapSampler::new();
-        let (norm_tex, height_tex, tang_tex, w, h) = Self::gen_texes(&hmap);
+        let (norm_tex, height_tex, tang_tex, height_map) = Self::gen_texes(&hmap, tracks);
+        let (w, h) = (height_map.width, height_map.height);
         let sand = RgbaTexture::from_file("images/sand.jpg").unwrap_or(RgbaTexture::unit());
         let sand_norm =
             RgbaTexture::from_file("images/sand_norm.jpg").unwrap_or(RgbaTexture::unit());
@@ -118,6 +172,7 @@ impl Terrain {
             tang_tex,
             sand_norm,
             hmap,
+            height_map,
         }
     }
 
@@ -125,6 +180,10 @@ impl Terrain {
         &self.height_tex
     }
 
+    pub fn height_map(&self) -> HeightMap {
+        self.height_map.clone()
+    }
+
     fn reload_shaders(&mut self, world: &mut StaticWorld) {
         match DrawShader::from_files("shaders/terrain.vert", "shaders/terrain.frag") {
             Ok(shader) => {
@@ -139,25 +198,63 @@ impl Terrain {
         }
     }
 
-    fn gen_texes(hmap: &HmapSampler) -> (RgbaTexture, FloatTexture, RgbaTexture, usize, usize) {
-        let level = RgbaTexture::load_rgba_image("images/level.png").unwrap();
-        let width = level.width() as usize;
-        let height = level.height() as usize;
+    // how much of the dunes is kept at each sample, nothing on the road and the pit lanes of
+    // the tracks, rising to all of it away from them
+    fn level_mask(tracks: &[String], width: usize, height: usize) -> Vec<f32> {
+        let mut mask = vec![1.0; width * height];
+        // sample (i, j) lies at x = j and z = i in texels, as in HeightMap::height_at
+        let texel = Vec2::new(TERRAIN_SIZE / height as f32, TERRAIN_SIZE / width as f32);
+        let mut level = |p: Vec2, radius: f32| {
+            let reach = radius + LEVEL_BLEND;
+            let cols = ((p.x - reach) / texel.x).max(0.0) as usize
+                ..((p.x + reach) / texel.x).clamp(0.0, height as f32) as usize;
+            let rows = ((p.y - reach) / texel.y).max(0.0) as usize
+                ..((p.y + reach) / texel.y).clamp(0.0, width as f32) as usize;
+            for i in rows {
+                for j in cols.clone() {
+                    let q = Vec2::new((j as f32 + 0.5) * texel.x, (i as f32 + 0.5) * texel.y);
+                    let f = (q - p).length().smoothstep(radius, reach);
+                    let m = &mut mask[i * height + j];
+                    *m = f32::min(*m, f);
+                }
+            }
+        };
+
+        for file in tracks {
+            let path = match Track::centreline(file) {
+                Ok(path) => path,
+                Err(e) => {
+                    println!("Failed to read path of {}: {:?}", file, e);
+                    continue;
+                }
+            };
+            let pit_lane = Track::pit_lane(file).unwrap_or(None);
+            let road = Track::track_width() / 2.0 + LEVEL_MARGIN;
+            for s in (0.0..path.length()).linspace((path.length() / LEVEL_STEP) as usize) {
+                level(path.point_at(s).0, road);
+                if let Some(pit) = pit_lane.filter(|pit| pit.contains(s, path.length())) {
+                    level(pit.lane_point(&path, s).0, pit.width / 2.0 + LEVEL_MARGIN);
+                }
+            }
+        }
+        mask
+    }
+
+    fn gen_texes(
+        hmap: &HmapSampler,
+        tracks: &[String],
+    ) -> (RgbaTexture, FloatTexture, RgbaTexture, HeightMap) {
+        let width = TERRAIN_SAMPLES;
+        let height = TERRAIN_SAMPLES;
+        let mask = Self::level_mask(tracks, width, height);
         let mut data = Vec::with_capacity(width * height);
         for x in (0.0..1.0f32).linspace(width) {
             for y in (0.0..1.0f32).linspace(height) {
                 data.push(hmap.get(Vec2::new(x, y)));
             }
         }
-
-        for i in 0..width {
-            for j in 0..height {
-                let f = level
-                    .get_pixel(height as u32 - 1 - j as u32, width as u32 - 1 - i as u32)
-                    .0[0] as f32
-                    / 255.0;
-                data[i * height + j] *= f;
-            }
+        for (h, f) in data.iter_mut().zip(mask.iter()) {
+            *h *= f;
         }
 
         let mut nrm_pxs = Vec::with_capacity(width * height);
@@ -185,8 +282,11 @@ impl Terrain {
             RgbaTexture::from_vec_v3_rescale(width, height, &nrm_pxs),
             FloatTexture::from_vec(width, height, &data),
             RgbaTexture::from_vec_v3_rescale(width, height, &tan_pxs),
-            width,
-            height,
+            HeightMap {
+                data,
+                width,
+                height,
+            },
         )
     }
 }
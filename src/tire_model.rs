@@ -0,0 +1,309 @@
+extern crate serde;
+
+use serde::{Deserialize, Serialize};
+
+const MIN_SLIP_SPEED: f32 = 3.0; // slip ratios are taken relative to at least this speed (m/s)
+const SLOPE_STEP: f32 = 0.01; // step of the angular speed for the slope of the tire force (rad/s)
+const PLOT_SAMPLES: usize = 100;
+
+/// Magic Formula curve `D sin(C atan(B x - E (B x - atan(B x))))` with the peak `D`
+/// given as a share of the grip of the car.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Curve {
+    pub stiffness: f32, // B, steepness around zero slip
+    pub shape: f32,     // C, how much of the peak is kept past it
+    pub peak: f32,      // D, share of the grip
+    pub curvature: f32, // E, sharpness of the peak
+}
+
+impl Curve {
+    pub fn eval(&self, slip: f32) -> f32 {
+        let bx = self.stiffness * slip;
+        self.peak * (self.shape * (bx - self.curvature * (bx - bx.atan())).atan()).sin()
+    }
+
+    /// Slip where the curve peaks.
+    pub fn peak_slip(&self) -> f32 {
+        let (mut lo, mut hi) = (0.0, 2.0);
+        for _ in 0..40 {
+            let mid = (lo + hi) / 2.0;
+            if self.eval(mid + 1e-4) > self.eval(mid) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo + hi) / 2.0
+    }
+}
+
+/// How the force of a tire follows its slip, as force per newton of load.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum TireModel {
+    /// Lateral force grows with the cornering stiffness of the car until it reaches the grip,
+    /// the wheels always roll and the engine and brakes push the car directly.
+    #[default]
+    Linear,
+    /// Magic Formula curves for the slip angle and the slip ratio of the spinning wheels,
+    /// combined in a friction ellipse.
+    MagicFormula { lateral: Curve, longitudinal: Curve },
+}
+
+impl TireModel {
+    /// The Magic Formula curves of the README.
+    #[cfg(test)]
+    pub fn magic_formula() -> Self {
+        TireModel::MagicFormula {
+            lateral: Curve {
+                stiffness: 6.0,
+                shape: 1.4,
+                peak: 1.0,
+                curvature: -0.2,
+            },
+            longitudinal: Curve {
+                stiffness: 12.0,
+                shape: 1.6,
+                peak: 0.25,
+                curvature: 0.0,
+            },
+        }
+    }
+
+    /// Lateral force against the slip angle (rad).
+    pub fn lateral(&self, slip_angle: f32, corner_stiffness: f32, grip: f32) -> f32 {
+        match self {
+            TireModel::Linear => (-corner_stiffness * slip_angle).clamp(-grip, grip),
+            TireModel::MagicFormula { lateral, .. } => -grip * lateral.eval(slip_angle),
+        }
+    }
+
+    /// Longitudinal force from the slip ratio, zero when the wheels are not simulated.
+    pub fn longitudinal(&self, slip_ratio: f32, grip: f32) -> f32 {
+        match self {
+            TireModel::Linear => 0.0,
+            TireModel::MagicFormula { longitudinal, .. } => grip * longitudinal.eval(slip_ratio),
+        }
+    }
+
+    /// Whether the wheels spin on their own and push the car through their slip.
+    pub fn spins_wheels(&self) -> bool {
+        matches!(self, TireModel::MagicFormula { .. })
+    }
+
+    /// Peak lateral and longitudinal forces for the given grip.
+    pub fn peaks(&self, grip: f32) -> (f32, f32) {
+        match self {
+            TireModel::Linear => (grip, grip),
+            TireModel::MagicFormula {
+                lateral,
+                longitudinal,
+            } => (grip * lateral.peak, grip * longitudinal.peak),
+        }
+    }
+
+    /// Slip ratio where the longitudinal force peaks.
+    pub fn peak_slip_ratio(&self) -> f32 {
+        match self {
+            TireModel::Linear => 1.0,
+            TireModel::MagicFormula { longitudinal, .. } => longitudinal.peak_slip(),
+        }
+    }
+}
+
+/// Angular speed of a wheel after `dt`, driven and braked by forces at its tread and pulled
+/// towards the speed of the road by its tire carrying `weight`. The tire force is taken
+/// implicitly so that the stiff wheel stays stable with the frame rate.
+pub fn spin_wheel(model: &TireModel, wheel: &Wheel, omega: f32, speed: f32, dt: f32) -> f32 {
+    let force =
+        |w: f32| model.longitudinal(slip_ratio(w * wheel.radius, speed), wheel.grip) * wheel.weight;
+    let f = force(omega);
+    let slope = ((force(omega + SLOPE_STEP) - f) / SLOPE_STEP).max(0.0);
+    let torque = (wheel.drive - f) * wheel.radius;
+    let mut next =
+        omega + dt * torque / wheel.inertia / (1.0 + dt * slope * wheel.radius / wheel.inertia);
+
+    // past the peak the slope gives no damping, the road pulls the wheel up to its speed but
+    // not beyond, only the engine can spin it faster
+    let road = speed / wheel.radius;
+    if (next - road) * (omega - road) < 0.0 && wheel.drive * (next - road) <= 0.0 {
+        next = road;
+    }
+
+    // brakes stop the wheel but never turn it backwards
+    let braking = wheel.brake * wheel.radius * dt / wheel.inertia;
+    if next.abs() <= braking {
+        0.0
+    } else {
+        next - braking * next.signum()
+    }
+}
+
+/// Forces on a wheel of an axle, at its tread.
+#[derive(Debug, Copy, Clone)]
+pub struct Wheel {
+    pub radius: f32,  // m
+    pub inertia: f32, // kg m^2
+    pub weight: f32,  // load on the tire (N)
+    pub grip: f32,    // grip of the tire (ratio)
+    pub drive: f32,   // force of the engine (N)
+    pub brake: f32,   // force of the brakes (N)
+}
+
+/// Angle between the heading of a tire and its path, taken against at least a walking pace
+/// so that it stays finite when the car stops.
+pub fn slip_angle(slide_speed: f32, roll_speed: f32) -> f32 {
+    slide_speed.atan2(roll_speed.abs().max(MIN_SLIP_SPEED))
+}
+
+/// Slip of a wheel turning at `wheel_speed` (m/s at the tread) on a road moving at `speed`.
+pub fn slip_ratio(wheel_speed: f32, speed: f32) -> f32 {
+    (wheel_speed - speed) / speed.abs().max(MIN_SLIP_SPEED)
+}
+
+/// Scales the longitudinal and lateral forces back onto the friction ellipse of their peaks.
+pub fn friction_ellipse(long: f32, lat: f32, peak_long: f32, peak_lat: f32) -> (f32, f32) {
+    let usage = (long / peak_long.max(1e-6)).powi(2) + (lat / peak_lat.max(1e-6)).powi(2);
+    if usage > 1.0 {
+        let scale = 1.0 / usage.sqrt();
+        (long * scale, lat * scale)
+    } else {
+        (long, lat)
+    }
+}
+
+/// Prints the curves of the model as CSV for tuning: slip, lateral force against the slip
+/// angle and longitudinal force against the slip ratio, per newton of load.
+pub fn print_curves(model: &TireModel, corner_stiffness: f32, grip: f32) {
+    println!("slip,lateral,longitudinal");
+    for i in 0..=PLOT_SAMPLES {
+        let slip = i as f32 / PLOT_SAMPLES as f32;
+        let longitudinal = if model.spins_wheels() {
+            model.longitudinal(slip, grip).to_string()
+        } else {
+            String::new()
+        };
+        println!(
+            "{},{},{}",
+            slip,
+            -model.lateral(slip, corner_stiffness, grip),
+            longitudinal
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn magic() -> (Curve, Curve) {
+        match TireModel::magic_formula() {
+            TireModel::MagicFormula {
+                lateral,
+                longitudinal,
+            } => (lateral, longitudinal),
+            TireModel::Linear => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn curves_are_odd_and_start_at_zero() {
+        for curve in [magic().0, magic().1].iter() {
+            assert_eq!(curve.eval(0.0), 0.0);
+            for x in [0.05, 0.2, 0.7].iter() {
+                assert!((curve.eval(*x) + curve.eval(-*x)).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn curves_peak_at_their_peak_and_fall_off_past_it() {
+        for curve in [magic().0, magic().1].iter() {
+            let at = curve.peak_slip();
+            assert!((curve.eval(at) - curve.peak).abs() < 1e-3);
+            assert!(curve.eval(at * 0.5) < curve.eval(at));
+            assert!(curve.eval(at * 3.0) < curve.eval(at));
+            assert!(curve.eval(at * 3.0) > 0.0);
+        }
+    }
+
+    #[test]
+    fn linear_model_is_the_default_and_clamped_stiffness() {
+        let model = TireModel::Linear;
+        assert_eq!(TireModel::default(), model);
+        assert_eq!(model.lateral(0.1, 14.0, 5.0), -1.4);
+        assert_eq!(model.lateral(-1.0, 14.0, 5.0), 5.0);
+        assert!(!model.spins_wheels());
+    }
+
+    #[test]
+    fn lateral_force_opposes_slip() {
+        let model = TireModel::magic_formula();
+        assert!(model.lateral(0.1, 14.0, 5.0) < 0.0);
+        assert!(model.lateral(-0.1, 14.0, 5.0) > 0.0);
+    }
+
+    #[test]
+    fn rolling_wheel_does_not_slip() {
+        assert_eq!(slip_ratio(20.0, 20.0), 0.0);
+        assert_eq!(slip_ratio(0.0, 20.0), -1.0);
+        assert!(slip_ratio(1.0, 0.0).is_finite());
+    }
+
+    #[test]
+    fn free_wheel_settles_at_road_speed() {
+        let model = TireModel::magic_formula();
+        let wheel = Wheel {
+            radius: 0.5,
+            inertia: 2.0,
+            weight: 4000.0,
+            grip: 5.0,
+            drive: 0.0,
+            brake: 0.0,
+        };
+        let mut omega = 0.0;
+        for _ in 0..120 {
+            omega = spin_wheel(&model, &wheel, omega, 30.0, 1.0 / 60.0);
+        }
+        assert!((omega * wheel.radius - 30.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn released_wheel_never_overtakes_the_road() {
+        let model = TireModel::magic_formula();
+        let wheel = Wheel {
+            radius: 0.5,
+            inertia: 2.0,
+            weight: 4000.0,
+            grip: 5.0,
+            drive: 0.0,
+            brake: 0.0,
+        };
+        for speed in 1..40 {
+            let speed = speed as f32;
+            let omega = spin_wheel(&model, &wheel, 0.0, speed, 1.0 / 60.0);
+            assert!(omega > 0.0 && omega * wheel.radius <= speed, "{}", speed);
+        }
+    }
+
+    #[test]
+    fn brakes_lock_but_never_reverse_the_wheel() {
+        let model = TireModel::magic_formula();
+        let wheel = Wheel {
+            radius: 0.5,
+            inertia: 2.0,
+            weight: 4000.0,
+            grip: 5.0,
+            drive: 0.0,
+            brake: 1e6,
+        };
+        assert_eq!(spin_wheel(&model, &wheel, 60.0, 30.0, 1.0 / 60.0), 0.0);
+    }
+
+    #[test]
+    fn ellipse_keeps_forces_within_the_peaks() {
+        assert_eq!(friction_ellipse(1.0, 1.0, 4.0, 4.0), (1.0, 1.0));
+        let (long, lat) = friction_ellipse(3.0, 4.0, 3.0, 4.0);
+        assert!(((long / 3.0).powi(2) + (lat / 4.0).powi(2) - 1.0).abs() < 1e-5);
+        assert!((long / lat - 0.75).abs() < 1e-5);
+    }
+}
@@ -16,13 +16,115 @@ use glui::tools::serde_tools::{SerdeError, SerdeJsonQuick};
 use glui::tools::texture::TextureFiltering;
 use glui::tools::texture_2d::ImageError;
 use notify::RecommendedWatcher;
+use serde::{Deserialize, Serialize};
 use std::sync::mpsc::Receiver;
 use std::time::Duration;
 
+pub const TRACK_FILE: &str = "race_track.json";
+
+const GRID_COLUMNS: [f32; 4] = [-5.4, -2.2, 2.2, 5.4]; // lateral grid positions in AI lane units
+const GRID_SETBACK: f32 = 12.0; // distance of the first row behind the start line (m)
+const GRID_ROW_SPACING: f32 = 18.0; // m
+const PIT_MARGIN: f32 = 2.0; // distance beside the pit lane still counted as in it (m)
+const PIT_SAMPLE_SPACING: f32 = 4.0; // m
+
+/// A lane beside the track, running from `entry` to `exit` with a stop box for service.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PitLane {
+    pub entry: f32,       // arc length of the pit entry from the start line (m)
+    pub exit: f32,        // arc length of the pit exit (m)
+    pub stop_box: f32,    // arc length of the box where cars are serviced (m)
+    pub offset: f32,      // distance of the lane from the centreline, positive to the right (m)
+    pub width: f32,       // m
+    pub speed_limit: f32, // m/s
+}
+
+impl Default for PitLane {
+    fn default() -> Self {
+        PitLane {
+            entry: 90.0,
+            exit: 320.0,
+            stop_box: 200.0,
+            offset: 18.4,
+            width: 8.0,
+            speed_limit: 16.0,
+        }
+    }
+}
+
+impl PitLane {
+    /// Whether the arc length is between the entry and the exit.
+    pub fn contains(&self, progress: f32, length: f32) -> bool {
+        let s = progress.rem_euclid(length);
+        let (entry, exit) = (self.entry.rem_euclid(length), self.exit.rem_euclid(length));
+        if entry <= exit {
+            s >= entry && s <= exit
+        } else {
+            s >= entry || s <= exit
+        }
+    }
+
+    /// Whether the point with the given projection onto the centreline is in the lane.
+    pub fn is_in(&self, proj: &TrackProjection, length: f32) -> bool {
+        self.contains(proj.progress, length)
+            && (proj.offset - self.offset).abs() < self.width / 2.0 + PIT_MARGIN
+    }
+
+    /// Distance still to drive to the stop box, negative once past it (m).
+    pub fn to_box(self, progress: f32, length: f32) -> f32 {
+        let d = (self.stop_box - progress).rem_euclid(length);
+        if d > length / 2.0 {
+            d - length
+        } else {
+            d
+        }
+    }
+
+    /// Point in the middle of the lane at the given arc length and the direction of travel.
+    pub fn lane_point(&self, path: &TrackPath, progress: f32) -> (Vec2, Vec2) {
+        let (p, dir) = path.point_at(progress);
+        (p + dir.perp() * self.offset, dir)
+    }
+}
+
+/// Contents of a track file, the bare control point coordinates or those with a pit lane.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TrackFile {
+    Points(Vec<f32>),
+    Layout {
+        points: Vec<f32>,
+        #[serde(default)]
+        pit_lane: Option<PitLane>,
+    },
+}
+
+impl TrackFile {
+    pub fn points(&self) -> &[f32] {
+        match self {
+            TrackFile::Points(points) => points,
+            TrackFile::Layout { points, .. } => points,
+        }
+    }
+
+    pub fn pit_lane(&self) -> Option<PitLane> {
+        match self {
+            TrackFile::Points(_) => None,
+            TrackFile::Layout { pit_lane, .. } => *pit_lane,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Message)]
 pub struct ShowWireframe(pub bool);
 
+/// Switches the layout to the given track file.
+#[derive(Clone, Debug, Message)]
+pub struct LoadTrack(pub String);
+
 pub struct Track {
+    file: String,
     track_entity: Entity,
     shader: DrawShader,
     channel: (RecommendedWatcher, Receiver<DebouncedEvent>),
@@ -33,7 +135,15 @@ impl System for Track {
     fn receive(&mut self, msg: &Box<dyn Message>, world: &mut StaticWorld) {
         if let Some(ShowWireframe(show)) = msg.downcast_ref::<ShowWireframe>() {
             if let Some(c) = world.component_mut::<DrawComponent>(self.track_entity) {
-                c.render_seq.command_mut(0).wireframe = *show;
+                for cmd in c.render_seq.command_iter_mut() {
+                    cmd.wireframe = *show;
+                }
+            }
+        }
+        if let Some(LoadTrack(file)) = msg.downcast_ref::<LoadTrack>() {
+            self.file = file.clone();
+            if let Err(e) = self.generate(world) {
+                println!("Failed to load track {}: {:?}", file, e);
             }
         }
     }
@@ -88,11 +198,12 @@ fn load_tex(path: &str) -> Result<RgbaTexture, ImageError> {
     Ok(tex)
 }
 impl Track {
-    pub fn new(world: &mut StaticWorld) -> Result<Track, TrackError> {
+    pub fn new(world: &mut StaticWorld, file: &str) -> Result<Track, TrackError> {
         let diffuse = load_tex("images/Road007_2K_Color_low.jpg")?;
         let normal = load_tex("images/Road007_2K_Normal_low.jpg")?;
         let roughness = load_tex("images/Road007_2K_Roughness_low.jpg")?;
         let mut track = Track {
+            file: file.to_owned(),
             track_entity: world.entity(),
             shader: DrawShader::from_files("shaders/track.vert", "shaders/track.frag")?,
             channel: watch(vec!["shaders/track.frag"])?,
@@ -103,6 +214,9 @@ impl Track {
     }
     fn fix_curves(mut pts: Vec<Vec2>) -> Vec<Vec2> {
         let n = pts.len();
+        if n < 4 {
+            return pts;
+        }
         pts[n - 1] = pts[0];
         let l = (pts[1] - pts[0]).length();
         let d = (pts[n - 1] - pts[n - 2]).sgn();
@@ -110,23 +224,39 @@ impl Track {
 
         pts
     }
-    pub fn track_curve_control_points() -> Result<Vec<Vec2>, SerdeError> {
-        let data_f32 = Vec::<f32>::load_json("race_track.json")?;
+    pub fn points_from_floats(data_f32: &[f32]) -> Vec<Vec2> {
         let mut data = Vec::with_capacity(data_f32.len() / 2);
         for i in 0..data_f32.len() / 2 {
             data.push(Vec2::new(data_f32[i * 2 + 0], data_f32[i * 2 + 1]));
         }
-        Ok(Self::fix_curves(data))
+        data
+    }
+    pub fn track_curve_control_points(file: &str) -> Result<Vec<Vec2>, SerdeError> {
+        let track = TrackFile::load_json(file)?;
+        Ok(Self::fix_curves(Self::points_from_floats(track.points())))
+    }
+    pub fn pit_lane(file: &str) -> Result<Option<PitLane>, SerdeError> {
+        Ok(TrackFile::load_json(file)?.pit_lane())
+    }
+    pub fn name(file: &str) -> String {
+        file.trim_end_matches(".json").to_owned()
     }
     pub fn track_width() -> f32 {
         3.6 * 8.0
     }
+    pub fn centreline(file: &str) -> Result<TrackPath, SerdeError> {
+        Ok(TrackPath::new(&Self::track_curve_control_points(file)?))
+    }
+    /// Lane of the AI driver starting from the given grid slot.
+    pub fn grid_lane(slot: usize) -> f32 {
+        GRID_COLUMNS[slot % GRID_COLUMNS.len()] / 2.0
+    }
     fn generate(&mut self, world: &mut StaticWorld) -> Result<(), SerdeError> {
         let mut pts = vec![];
         let mut tpts = vec![];
         let mut tang = vec![];
         let track_width = Self::track_width();
-        let data = Self::track_curve_control_points()?;
+        let data = Self::track_curve_control_points(&self.file)?;
         let mut i = 0;
         let mut last_p = Vec2::zero();
         let mut curve_len = 0.0;
@@ -160,6 +290,47 @@ impl Track {
             i += 3;
         }
 
+        let mut render_seq = RenderSequence::new();
+        self.add_strip(&mut render_seq, pts, tpts, tang, sample_count);
+
+        // the pit lane is a narrower strip beside the track
+        if let Some(pit) = TrackFile::load_json(&self.file)?.pit_lane() {
+            let path = TrackPath::new(&data);
+            let length = path.length();
+            let span = (pit.exit - pit.entry).rem_euclid(length);
+            let samples = (span / PIT_SAMPLE_SPACING).ceil() as usize + 1;
+            let (mut pts, mut tpts, mut tang) = (vec![], vec![], vec![]);
+
+            for k in 0..samples {
+                let along = span * k as f32 / (samples - 1) as f32;
+                let (p, dir) = pit.lane_point(&path, pit.entry + along);
+                let n = dir.perp();
+                for (side, u) in [(-0.5, 0.0), (0.0, 0.5), (0.5, 1.0)].iter() {
+                    pts.push(Vec3::from_vec2(p + n * pit.width * *side, 0.0).xzy());
+                    tpts.push(Vec2::new(*u, along / pit.width));
+                    tang.push(Vec3::from_vec2(n, 0.0).xzy());
+                }
+            }
+            self.add_strip(&mut render_seq, pts, tpts, tang, samples);
+        }
+
+        let comp = DrawComponent {
+            render_seq,
+            model_matrix: Mat4::identity(),
+        };
+
+        world.add_component(self.track_entity, comp);
+
+        Ok(())
+    }
+    fn add_strip(
+        &self,
+        render_seq: &mut RenderSequence,
+        pts: Vec<Vec3>,
+        tpts: Vec<Vec2>,
+        tang: Vec<Vec3>,
+        sample_count: usize,
+    ) {
         let inds = parsurf_indices_triangulated(3, sample_count);
 
         let pbuf = Buffer::from_vec(&pts);
@@ -173,8 +344,6 @@ impl Track {
 
         vao.set_indices_buffer(&ibuf);
 
-        let mut render_seq = RenderSequence::new();
-
         render_seq.add_buffer(pbuf.into_base_type());
         render_seq.add_buffer(tbuf.into_base_type());
         render_seq.add_buffer(gbuf.into_base_type());
@@ -192,22 +361,15 @@ impl Track {
             ],
         );
         render_seq.add_command(cmd);
-
-        let comp = DrawComponent {
-            render_seq,
-            model_matrix: Mat4::identity(),
-        };
-
-        world.add_component(self.track_entity, comp);
-
-        Ok(())
     }
     fn reload_shaders(&mut self, world: &mut StaticWorld) {
         match DrawShader::from_files("shaders/track.vert", "shaders/track.frag") {
             Ok(shader) => {
                 self.shader = shader;
                 if let Some(comp) = world.component_mut::<DrawComponent>(self.track_entity) {
-                    comp.render_seq.command_mut(0).shader = self.shader.clone().into();
+                    for cmd in comp.render_seq.command_iter_mut() {
+                        cmd.shader = self.shader.clone().into();
+                    }
                 }
             }
             Err(e) => {
@@ -216,3 +378,152 @@ impl Track {
         }
     }
 }
+
+#[derive(Debug, Copy, Clone)]
+pub struct TrackProjection {
+    pub progress: f32, // arc length from the start line (m)
+    pub offset: f32,   // signed distance from the centreline, positive to the right (m)
+    pub dir: Vec2,     // direction of travel at the projected point
+}
+
+/// Densely sampled centreline of the track used for progress queries.
+#[derive(Debug, Clone)]
+pub struct TrackPath {
+    points: Vec<Vec2>,
+    arc: Vec<f32>,
+    length: f32,
+}
+
+impl TrackPath {
+    pub fn new(data: &[Vec2]) -> TrackPath {
+        let mut points: Vec<Vec2> = vec![];
+        let mut i = 0;
+        while i + 4 <= data.len() {
+            for t in (0.0..1.0).linspace(20) {
+                let (p, _v, _n) =
+                    Vec2::eval_bezier4(data[i], data[i + 1], data[i + 2], data[i + 3], t);
+                if points
+                    .last()
+                    .map(|l| (*l - p).length() > 0.001)
+                    .unwrap_or(true)
+                {
+                    points.push(p);
+                }
+            }
+            i += 3;
+        }
+        if points.len() > 1 && (points[0] - points[points.len() - 1]).length() < 0.001 {
+            points.pop();
+        }
+
+        let mut arc = Vec::with_capacity(points.len());
+        let mut length = 0.0;
+        for i in 0..points.len() {
+            arc.push(length);
+            length += (points[(i + 1) % points.len()] - points[i]).length();
+        }
+
+        TrackPath {
+            points,
+            arc,
+            length,
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    pub fn project(&self, p: Vec2) -> TrackProjection {
+        let n = self.points.len();
+        let mut best = (f32::INFINITY, 0, 0.0);
+
+        for i in 0..n {
+            let a = self.points[i];
+            let b = self.points[(i + 1) % n];
+            let ab = b - a;
+            let t = ((p - a).dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+            let d = (a + ab * t - p).length();
+            if d < best.0 {
+                best = (d, i, t);
+            }
+        }
+
+        let (_, i, t) = best;
+        let a = self.points[i];
+        let b = self.points[(i + 1) % n];
+        let dir = (b - a).sgn();
+
+        TrackProjection {
+            progress: self.arc[i] + (b - a).length() * t,
+            offset: (p - a).dot(dir.perp()),
+            dir,
+        }
+    }
+
+    /// Position and direction of travel at the given arc length.
+    pub fn point_at(&self, progress: f32) -> (Vec2, Vec2) {
+        let n = self.points.len();
+        let s = progress.rem_euclid(self.length);
+        let mut i = 0;
+        while i + 1 < n && self.arc[i + 1] <= s {
+            i += 1;
+        }
+        let a = self.points[i];
+        let b = self.points[(i + 1) % n];
+        let seg = (b - a).length();
+        let t = if seg > 0.0 {
+            (s - self.arc[i]) / seg
+        } else {
+            0.0
+        };
+
+        (a + (b - a) * t, (b - a).sgn())
+    }
+
+    /// Heading and position of a starting grid slot, four cars abreast behind the start line.
+    pub fn grid_slot(&self, slot: usize) -> (f32, Vec2) {
+        let row = slot / GRID_COLUMNS.len();
+        let col = slot % GRID_COLUMNS.len();
+        let (p, dir) = self.point_at(-GRID_SETBACK - GRID_ROW_SPACING * row as f32);
+
+        (
+            dir.y.atan2(dir.x),
+            p + dir.perp() * (GRID_COLUMNS[col] * 1.5 - 1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LENGTH: f32 = 1000.0;
+
+    #[test]
+    fn the_lane_runs_from_entry_to_exit() {
+        let pit = PitLane::default();
+        assert!(pit.contains(100.0, LENGTH));
+        assert!(pit.contains(1100.0, LENGTH));
+        assert!(!pit.contains(50.0, LENGTH));
+        assert!(!pit.contains(500.0, LENGTH));
+
+        let across_the_line = PitLane {
+            entry: 900.0,
+            exit: 100.0,
+            ..pit
+        };
+        assert!(across_the_line.contains(950.0, LENGTH));
+        assert!(across_the_line.contains(50.0, LENGTH));
+        assert!(!across_the_line.contains(500.0, LENGTH));
+    }
+
+    #[test]
+    fn the_box_is_ahead_then_behind() {
+        let pit = PitLane::default();
+        assert_eq!(pit.to_box(150.0, LENGTH), 50.0);
+        assert_eq!(pit.to_box(1150.0, LENGTH), 50.0);
+        assert_eq!(pit.to_box(250.0, LENGTH), -50.0);
+        assert_eq!(pit.to_box(-850.0, LENGTH), 50.0);
+    }
+}
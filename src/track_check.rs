@@ -0,0 +1,274 @@
+use crate::track::{Track, TrackFile};
+use glui::tools::serde_tools::SerdeJsonQuick;
+use glui::tools::{LinSpace, Vec2};
+use std::f32::consts::PI;
+use std::fmt;
+
+const CLOSE_EPS: f32 = 0.01; // max distance between first and last point (m)
+const MAX_TANGENT_KINK: f32 = PI / 12.0; // max angle between tangents at a joint (rad)
+const SAMPLES_PER_SEGMENT: usize = 20; // same resolution as the generated track mesh
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackIssue {
+    OddCoordinateCount(usize),
+    PointCount(usize),
+    NotClosed(f32),
+    TangentBreak { segment: usize, angle: f32 },
+    SelfIntersection { segment_a: usize, segment_b: usize },
+    TightRadius { segment: usize, radius: f32 },
+}
+
+impl fmt::Display for TrackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackIssue::OddCoordinateCount(n) => {
+                write!(f, "odd number of coordinates ({}), points are x,y pairs", n)
+            }
+            TrackIssue::PointCount(n) => write!(
+                f,
+                "{} control points, expected 3k+1 with at least one segment",
+                n
+            ),
+            TrackIssue::NotClosed(d) => {
+                write!(
+                    f,
+                    "loop is not closed, last point is {:.2} m from the first",
+                    d
+                )
+            }
+            TrackIssue::TangentBreak { segment, angle } => write!(
+                f,
+                "segment {}: tangent breaks by {:.1}° at its start",
+                segment,
+                angle.to_degrees()
+            ),
+            TrackIssue::SelfIntersection {
+                segment_a,
+                segment_b,
+            } => write!(f, "segment {} intersects segment {}", segment_a, segment_b),
+            TrackIssue::TightRadius { segment, radius } => write!(
+                f,
+                "segment {}: radius {:.1} m is below half the track width ({:.1} m)",
+                segment,
+                radius,
+                Track::track_width() / 2.0
+            ),
+        }
+    }
+}
+
+fn cross(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+// first and second derivative of a cubic bezier
+fn bezier_derivatives(p: &[Vec2], t: f32) -> (Vec2, Vec2) {
+    let u = 1.0 - t;
+    let d1 = (p[1] - p[0]) * (3.0 * u * u)
+        + (p[2] - p[1]) * (6.0 * u * t)
+        + (p[3] - p[2]) * (3.0 * t * t);
+    let d2 = (p[2] - p[1] * 2.0 + p[0]) * (6.0 * u) + (p[3] - p[2] * 2.0 + p[1]) * (6.0 * t);
+    (d1, d2)
+}
+
+fn segments_intersect(a0: Vec2, a1: Vec2, b0: Vec2, b1: Vec2) -> bool {
+    let da = a1 - a0;
+    let db = b1 - b0;
+    let d1 = cross(da, b0 - a0);
+    let d2 = cross(da, b1 - a0);
+    let d3 = cross(db, a0 - b0);
+    let d4 = cross(db, a1 - b0);
+
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+/// Checks raw control points of a track, returns every problem found.
+pub fn check_track(pts: &[Vec2], track_width: f32) -> Vec<TrackIssue> {
+    let mut issues = vec![];
+    let n = pts.len();
+
+    if n < 4 || !(n - 1).is_multiple_of(3) {
+        issues.push(TrackIssue::PointCount(n));
+        return issues;
+    }
+    let segments = (n - 1) / 3;
+
+    let gap = (pts[n - 1] - pts[0]).length();
+    if gap > CLOSE_EPS {
+        issues.push(TrackIssue::NotClosed(gap));
+    }
+
+    for s in 0..segments {
+        let i = s * 3;
+        let incoming = if i == 0 {
+            pts[n - 1] - pts[n - 2]
+        } else {
+            pts[i] - pts[i - 1]
+        };
+        let outgoing = pts[i + 1] - pts[i];
+
+        let angle = if incoming.length() < CLOSE_EPS || outgoing.length() < CLOSE_EPS {
+            PI
+        } else {
+            incoming.sgn().dot(outgoing.sgn()).clamp(-1.0, 1.0).acos()
+        };
+        if angle > MAX_TANGENT_KINK {
+            issues.push(TrackIssue::TangentBreak { segment: s, angle });
+        }
+    }
+
+    let mut polyline = vec![];
+    let mut min_radius = vec![f32::INFINITY; segments];
+    for s in 0..segments {
+        let p = &pts[s * 3..s * 3 + 4];
+        for t in (0.0..1.0).linspace(SAMPLES_PER_SEGMENT) {
+            let (pos, _v, _n) = Vec2::eval_bezier4(p[0], p[1], p[2], p[3], t);
+            polyline.push((pos, s));
+
+            let (d1, d2) = bezier_derivatives(p, t);
+            let c = cross(d1, d2).abs();
+            if c > 1e-6 {
+                let r = d1.length().powi(3) / c;
+                min_radius[s] = min_radius[s].min(r);
+            }
+        }
+    }
+
+    for (s, r) in min_radius.into_iter().enumerate() {
+        if r < track_width / 2.0 {
+            issues.push(TrackIssue::TightRadius {
+                segment: s,
+                radius: r,
+            });
+        }
+    }
+
+    let m = polyline.len();
+    let mut reported = vec![];
+    for i in 0..m {
+        let (a0, sa) = polyline[i];
+        let (a1, _) = polyline[(i + 1) % m];
+        for j in i + 2..m {
+            if (j + 1) % m == i {
+                continue;
+            }
+            let (b0, sb) = polyline[j];
+            let (b1, _) = polyline[(j + 1) % m];
+            if segments_intersect(a0, a1, b0, b1) && !reported.contains(&(sa, sb)) {
+                reported.push((sa, sb));
+                issues.push(TrackIssue::SelfIntersection {
+                    segment_a: sa,
+                    segment_b: sb,
+                });
+            }
+        }
+    }
+
+    issues
+}
+
+/// Entry point of `skyrace check-track`, returns the process exit code.
+pub fn run(path: &str) -> i32 {
+    let data = match TrackFile::load_json(path) {
+        Ok(track) => track.points().to_vec(),
+        Err(e) => {
+            eprintln!("Failed to read {}: {:?}", path, e);
+            return 2;
+        }
+    };
+
+    let issues = if data.len() % 2 != 0 {
+        vec![TrackIssue::OddCoordinateCount(data.len())]
+    } else {
+        check_track(&Track::points_from_floats(&data), Track::track_width())
+    };
+
+    if issues.is_empty() {
+        println!("{}: OK", path);
+        0
+    } else {
+        for issue in issues.iter() {
+            eprintln!("{}: {}", path, issue);
+        }
+        eprintln!("{}: {} problem(s) found", path, issues.len());
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: f32 = 28.8;
+
+    fn v(x: f32, y: f32) -> Vec2 {
+        Vec2::new(x, y)
+    }
+
+    // a circle of four quarter arcs
+    fn circle(r: f32) -> Vec<Vec2> {
+        let k = 0.5523 * r;
+        vec![
+            v(r, 0.0),
+            v(r, k),
+            v(k, r),
+            v(0.0, r),
+            v(-k, r),
+            v(-r, k),
+            v(-r, 0.0),
+            v(-r, -k),
+            v(-k, -r),
+            v(0.0, -r),
+            v(k, -r),
+            v(r, -k),
+            v(r, 0.0),
+        ]
+    }
+
+    #[test]
+    fn a_round_track_is_valid() {
+        assert_eq!(check_track(&circle(200.0), WIDTH), vec![]);
+    }
+
+    #[test]
+    fn a_bow_tie_intersects_itself() {
+        let d = 200.0;
+        let third = d * 2.0 / 3.0;
+        let pts = vec![
+            v(-d, -d),
+            v(-d + third, -d + third),
+            v(d - third, d - third),
+            v(d, d),
+            v(d + 100.0, d + 100.0),
+            v(d + 100.0, -d - 100.0),
+            v(d, -d),
+            v(d - third, -d + third),
+            v(-d + third, d - third),
+            v(-d, d),
+            v(-d - 100.0, d + 100.0),
+            v(-d - 100.0, -d - 100.0),
+            v(-d, -d),
+        ];
+        let issues = check_track(&pts, WIDTH);
+        assert!(issues.contains(&TrackIssue::SelfIntersection {
+            segment_a: 0,
+            segment_b: 2
+        }));
+    }
+
+    #[test]
+    fn degenerate_tracks_are_rejected() {
+        let pts = circle(200.0);
+        assert_eq!(
+            check_track(&pts[..3], WIDTH),
+            vec![TrackIssue::PointCount(3)]
+        );
+        assert_eq!(
+            check_track(&pts[..6], WIDTH),
+            vec![TrackIssue::PointCount(6)]
+        );
+
+        let open = check_track(&pts[..10], WIDTH);
+        assert!(matches!(open[0], TrackIssue::NotClosed(_)));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::car::CarComponent;
+use crate::race_control::RaceStatus;
+use crate::track::{Track, TrackPath};
+
+use glui::mecs::{Entity, StaticWorld};
+use glui::tools::{Vec2, Vec3};
+
+const CORNER_SPAN: f32 = 30.0; // length over which the turn of the track is measured (m)
+const CORNER_ANGLE: f32 = 0.35; // minimal turn of a corner that gets a camera (rad)
+const SAMPLE_STEP: f32 = 5.0; // m
+const MAX_CAMERA_GAP: f32 = 220.0; // straights longer than this get extra cameras (m)
+const CAMERA_SETBACK: f32 = 14.0; // distance of the cameras from the edge of the track (m)
+const CAMERA_HEIGHT: f32 = 6.0; // m
+const MIN_SHOT_TIME: f32 = 2.5; // no cuts to another camera before this (s)
+const MIN_CAR_TIME: f32 = 6.0; // no change of the followed car before this (s)
+const HOLD_TIME: f32 = 15.0; // time a manually picked car is kept (s)
+const SWITCH_MARGIN: f32 = 0.3; // how much more interesting another car has to be
+const BATTLE_GAP: f32 = 20.0; // cars closer than this are fighting (m)
+const OVERTAKE_BONUS: f32 = 2.0;
+const SPIN_BONUS: f32 = 3.0;
+const LEADER_BONUS: f32 = 0.2;
+const INTEREST_DECAY: f32 = 0.4; // per second
+const FRAME_WIDTH: f32 = 22.0; // width of the scene kept in frame around the car (m)
+const MIN_FOV: f32 = 0.08; // rad
+const TRACKING: f32 = 6.0; // responsiveness of aim and zoom (1/s)
+
+/// What the camera should show this frame.
+pub struct Shot {
+    pub car: usize,
+    pub pos: Vec3,
+    pub target: Vec3,
+    pub fov: f32,
+}
+
+/// Broadcast style camera work: trackside cameras, automatic cuts and
+/// choosing the car worth watching.
+pub struct TvDirector {
+    cameras: Vec<Vec3>,
+    camera: Option<usize>,
+    car: usize,
+    shot_time: f32,
+    car_time: f32,
+    hold: f32,
+    interest: Vec<f32>, // decaying bonus of recent overtakes and spins
+    order: Vec<usize>,
+    target: Vec3,
+    fov: f32,
+    max_fov: f32, // the widest view, that of the camera without zoom
+}
+
+impl TvDirector {
+    pub fn new(path: &TrackPath, car_count: usize, max_fov: f32) -> TvDirector {
+        TvDirector {
+            cameras: Self::trackside_cameras(path),
+            camera: None,
+            car: 0,
+            shot_time: 0.0,
+            car_time: 0.0,
+            hold: 0.0,
+            interest: vec![0.0; car_count],
+            order: (0..car_count).collect(),
+            target: Vec3::zero(),
+            fov: max_fov,
+            max_fov,
+        }
+    }
+
+    /// Cameras at the outside of every corner, plus some along long straights.
+    pub fn trackside_cameras(path: &TrackPath) -> Vec<Vec3> {
+        let length = path.length();
+        let half_width = Track::track_width() / 2.0;
+        let samples = (length / SAMPLE_STEP) as usize;
+
+        // turn of the track around each sample, the change of direction points inside the corner
+        let turns = (0..samples)
+            .map(|i| {
+                let s = i as f32 * SAMPLE_STEP;
+                let (_, d0) = path.point_at(s - CORNER_SPAN / 2.0);
+                let (_, d1) = path.point_at(s + CORNER_SPAN / 2.0);
+                (d0.dot(d1).clamp(-1.0, 1.0).acos(), d1 - d0)
+            })
+            .collect::<Vec<_>>();
+
+        let mut placed: Vec<(f32, Vec2)> = vec![];
+        for i in 0..samples {
+            let (angle, _) = turns[i];
+            let prev = turns[(i + samples - 1) % samples].0;
+            let next = turns[(i + 1) % samples].0;
+            if angle > CORNER_ANGLE && angle >= prev && angle > next {
+                let s = i as f32 * SAMPLE_STEP;
+                let (p, _) = path.point_at(s);
+                let outside = turns[i].1.sgn() * -1.0;
+                placed.push((s, p + outside * (half_width + CAMERA_SETBACK)));
+            }
+        }
+
+        // fill the long gaps between corners
+        let mut cameras = vec![];
+        for i in 0..placed.len().max(1) {
+            let (s, p) = placed.get(i).copied().unwrap_or((0.0, Vec2::zero()));
+            let next_s = placed
+                .get(i + 1)
+                .map(|c| c.0)
+                .unwrap_or_else(|| placed.first().map(|c| c.0).unwrap_or(0.0) + length);
+            if !placed.is_empty() {
+                cameras.push(p);
+            }
+
+            let extra = ((next_s - s) / MAX_CAMERA_GAP).floor() as usize;
+            for k in 1..=extra {
+                let (q, dir) = path.point_at(s + (next_s - s) * k as f32 / (extra + 1) as f32);
+                cameras.push(q + dir.perp() * (half_width + CAMERA_SETBACK));
+            }
+        }
+
+        // a camera must not stand on another part of the track
+        cameras
+            .into_iter()
+            .filter(|c| path.project(*c).offset.abs() > half_width + 2.0)
+            .map(|c| Vec3::new(c.x, CAMERA_HEIGHT, c.y))
+            .collect()
+    }
+
+    /// Keeps following the given car for a while.
+    pub fn hold(&mut self, car: usize) {
+        if car != self.car {
+            self.car = car;
+            self.car_time = 0.0;
+            self.camera = None;
+        }
+        self.hold = HOLD_TIME;
+    }
+
+    pub fn update(&mut self, dt: f32, cars: &[Entity], world: &StaticWorld) -> Shot {
+        self.shot_time += dt;
+        self.car_time += dt;
+        self.hold -= dt;
+
+        let scores = self.rate_cars(dt, cars, world);
+
+        let gone = scores[self.car] == f32::NEG_INFINITY;
+        if gone || (self.hold <= 0.0 && self.car_time > MIN_CAR_TIME) {
+            let best = (0..cars.len())
+                .max_by(|a, b| scores[*a].partial_cmp(&scores[*b]).unwrap())
+                .unwrap_or(0);
+            if scores[best] > scores[self.car] + SWITCH_MARGIN {
+                self.car = best;
+                self.car_time = 0.0;
+                self.camera = None;
+            }
+        }
+
+        let car_pos = world
+            .component::<CarComponent>(cars[self.car])
+            .map(|car| car.pos3())
+            .unwrap_or(self.target);
+
+        let nearest = (0..self.cameras.len()).min_by(|a, b| {
+            let da = (self.cameras[*a] - car_pos).length();
+            let db = (self.cameras[*b] - car_pos).length();
+            da.partial_cmp(&db).unwrap()
+        });
+
+        let cut = match (self.camera, nearest) {
+            (None, _) => true,
+            (Some(c), Some(n)) => c != n && self.shot_time > MIN_SHOT_TIME,
+            _ => false,
+        };
+        if cut {
+            self.camera = nearest;
+            self.shot_time = 0.0;
+        }
+
+        let pos = match self.camera {
+            Some(c) => self.cameras[c],
+            None => car_pos + Vec3::new(0.0, 30.0, 0.0),
+        };
+
+        let dist = (pos - car_pos).length().max(1.0);
+        let fov = (2.0 * (FRAME_WIDTH / 2.0 / dist).atan())
+            .max(MIN_FOV)
+            .min(self.max_fov);
+        let smooth = 1.0 - (-dt * TRACKING).exp();
+
+        if cut {
+            self.target = car_pos;
+            self.fov = fov;
+        } else {
+            self.target = self.target + (car_pos - self.target) * smooth;
+            self.fov += (fov - self.fov) * smooth;
+        }
+
+        Shot {
+            car: self.car,
+            pos,
+            target: self.target,
+            fov: self.fov,
+        }
+    }
+
+    fn rate_cars(&mut self, dt: f32, cars: &[Entity], world: &StaticWorld) -> Vec<f32> {
+        let progress = cars
+            .iter()
+            .map(|e| {
+                world
+                    .component::<RaceStatus>(*e)
+                    .map(|s| s.progress)
+                    .unwrap_or(0.0)
+            })
+            .collect::<Vec<_>>();
+
+        let mut order = (0..cars.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| progress[*b].partial_cmp(&progress[*a]).unwrap());
+
+        for i in 0..cars.len() {
+            self.interest[i] = (self.interest[i] - INTEREST_DECAY * dt).max(0.0);
+        }
+
+        // overtakes: a car got ahead of the one it was behind
+        for (place, car) in order.iter().enumerate() {
+            let old_place = self.order.iter().position(|c| c == car).unwrap_or(place);
+            if place < old_place {
+                self.interest[*car] = self.interest[*car].max(OVERTAKE_BONUS);
+            }
+        }
+        self.order = order.clone();
+
+        // spins: moving sideways or rotating fast
+        for (i, e) in cars.iter().enumerate() {
+            let car = match world.component::<CarComponent>(*e) {
+                Some(car) => car,
+                None => continue,
+            };
+            let speed = car.speed();
+            let sliding = speed > 5.0 && car.dir().dot(car.velocity / speed) < 0.7;
+            if sliding || car.angular_velocity.abs() > 2.0 {
+                self.interest[i] = self.interest[i].max(SPIN_BONUS);
+            }
+        }
+
+        let mut scores = self.interest.clone();
+        for k in 0..order.len() {
+            let mut battle: f32 = 0.0;
+            if k > 0 {
+                battle = battle.max(BATTLE_GAP - (progress[order[k - 1]] - progress[order[k]]));
+            }
+            if k + 1 < order.len() {
+                battle = battle.max(BATTLE_GAP - (progress[order[k]] - progress[order[k + 1]]));
+            }
+            scores[order[k]] += battle.max(0.0) / BATTLE_GAP;
+        }
+        if let Some(leader) = order.first() {
+            scores[*leader] += LEADER_BONUS;
+        }
+        // cars taken off the track are not shown
+        for (i, e) in cars.iter().enumerate() {
+            if world.component::<CarComponent>(*e).is_none() {
+                scores[i] = f32::NEG_INFINITY;
+            }
+        }
+
+        scores
+    }
+}
@@ -18,7 +18,10 @@ pub struct WheelMark {
 impl System for WheelMark {
     fn update(&mut self, _delta_time: Duration, world: &mut StaticWorld) {
         for j in 0..self.cars.len() {
-            let car = world.component::<CarComponent>(self.cars[j]).unwrap();
+            let car = match world.component::<CarComponent>(self.cars[j]) {
+                Some(car) => car,
+                None => continue,
+            };
             let p = car.pos3();
             let f = car.dir3();
             let r = car.right3();
@@ -31,7 +34,7 @@ impl System for WheelMark {
 
             let dotp = car.dir().dot(car.velocity.sgn());
 
-            if dotp < 0.9 {
+            if dotp < 0.9 && !car.airborne {
                 for i in 0..4 {
                     let p = wheel_p[i];
                     let prevp = self.prev_pos[j][i];